@@ -0,0 +1,29 @@
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=src/main.rs");
+    println!("cargo:rerun-if-env-changed=DEFMT_LOG");
+    if std::env::var_os("CARGO_FEATURE_DEFMT").is_some() {
+        // defmt filters at compile time, so give it the same directives the runtime filter starts
+        // from. DEFMT_LOG in the environment still wins.
+        if std::env::var_os("DEFMT_LOG").is_none() {
+            println!("cargo:rustc-env=DEFMT_LOG={}", log_filter());
+        }
+        // defmt's linker script lays out the interned strings; hosted builds link them as is.
+        if std::env::var("CARGO_CFG_TARGET_ARCH").as_deref() == Ok("xtensa") {
+            println!("cargo:rustc-link-arg=-Wl,-Tdefmt.x");
+        }
+    }
+}
+
+// The `LOG_FILTER` string literal in src/main.rs.
+fn log_filter() -> String {
+    let main = std::fs::read_to_string("src/main.rs").expect("reading src/main.rs");
+    main.lines()
+        .find_map(|line| {
+            line.trim()
+                .strip_prefix("const LOG_FILTER: &str = \"")?
+                .split_once('"')
+                .map(|(spec, _)| spec.to_string())
+        })
+        .expect("src/main.rs has no `const LOG_FILTER: &str = \"...\"`")
+}
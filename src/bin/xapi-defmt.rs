@@ -0,0 +1,403 @@
+//! Turns the defmt stream written by a hosted `--features defmt` build back into text, using the
+//! format strings interned in the binary.
+//!
+//! ```text
+//! cargo run --target x86_64-unknown-linux-gnu --features defmt_decode --bin xapi-defmt -- \
+//!     target/x86_64-unknown-linux-gnu/debug/xapi_rs [.defmt_stream]
+//! ```
+//!
+//! Frames the tool cannot make sense of are reported and skipped.
+use defmt_parser::{DisplayHint, Fragment, ParserMode, TimePrecision, Type};
+use object::{Object, ObjectSymbol};
+use std::collections::HashMap;
+use std::process::ExitCode;
+
+const STREAM_MAGIC: &[u8; 4] = b"XDF1";
+const ANCHOR: &str = "XAPI_DEFMT_ANCHOR";
+
+struct Interned {
+    tag: String,
+    format: String,
+}
+
+/// Interned strings by the index they are logged under.
+struct Table {
+    strings: HashMap<u16, Interned>,
+    timestamp: u16,
+}
+
+impl Table {
+    fn load(elf: &[u8], anchor: u64, timestamp: u16) -> Result<Table, String> {
+        let file = object::File::parse(elf).map_err(|e| e.to_string())?;
+        let mut symbols = Vec::new();
+        let mut elf_anchor = None;
+        for symbol in file.symbols() {
+            let Ok(name) = symbol.name() else { continue };
+            // Mach-O prefixes every symbol with an underscore.
+            let name = name
+                .strip_prefix('_')
+                .filter(|n| n.starts_with('{') || *n == ANCHOR)
+                .unwrap_or(name);
+            match name {
+                ANCHOR => elf_anchor = Some(symbol.address()),
+                json if json.starts_with('{') => {
+                    if let Ok(json) = serde_json::from_str::<serde_json::Value>(json) {
+                        symbols.push((symbol.address(), json));
+                    }
+                }
+                _ => {}
+            }
+        }
+        let elf_anchor = elf_anchor.ok_or("no XAPI_DEFMT_ANCHOR symbol; built without defmt?")?;
+        let slide = anchor.wrapping_sub(elf_anchor);
+        let mut table = Table {
+            strings: HashMap::new(),
+            timestamp,
+        };
+        for (address, json) in symbols {
+            let (Some(tag), Some(format)) = (json["tag"].as_str(), json["data"].as_str()) else {
+                continue;
+            };
+            let index = address.wrapping_add(slide) as u16;
+            let interned = Interned {
+                tag: tag.into(),
+                format: format.into(),
+            };
+            if let Some(other) = table.strings.insert(index, interned) {
+                eprintln!(
+                    "warning: strings collide at index {index:#06x}: {:?}",
+                    other.format
+                );
+            }
+        }
+        Ok(table)
+    }
+
+    fn get(&self, index: u16) -> Result<&Interned, String> {
+        self.strings
+            .get(&index)
+            .ok_or_else(|| format!("unknown string index {index:#06x}"))
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.0.len() < len {
+            return Err("frame ends early".into());
+        }
+        let (head, tail) = self.0.split_at(len);
+        self.0 = tail;
+        Ok(head)
+    }
+
+    fn uint(&mut self, len: usize) -> Result<u128, String> {
+        let mut value = [0; 16];
+        value[..len].copy_from_slice(self.bytes(len)?);
+        Ok(u128::from_le_bytes(value))
+    }
+
+    fn int(&mut self, len: usize) -> Result<i128, String> {
+        let shift = 128 - 8 * len as u32;
+        Ok((self.uint(len)? as i128) << shift >> shift)
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        Ok(self.uint(2)? as u16)
+    }
+
+    fn len(&mut self) -> Result<usize, String> {
+        Ok(self.uint(4)? as usize)
+    }
+
+    // `Display2Format` and `Debug2Format` output, terminated by 0xff.
+    fn formatted(&mut self) -> Result<String, String> {
+        let end = self
+            .0
+            .iter()
+            .position(|b| *b == 0xff)
+            .ok_or("unterminated formatted argument")?;
+        let text = String::from_utf8_lossy(&self.0[..end]).into_owned();
+        self.0 = &self.0[end + 1..];
+        Ok(text)
+    }
+}
+
+enum Value {
+    Unsigned(u128),
+    Signed(i128),
+    Float(f64),
+    Text(String),
+    Bytes(Vec<u8>),
+}
+
+fn render(value: &Value, hint: Option<&DisplayHint>) -> String {
+    let seconds = |v: u128, precision: &TimePrecision| match precision {
+        TimePrecision::Micros => format!("{}.{:06}", v / 1_000_000, v % 1_000_000),
+        TimePrecision::Millis => format!("{}.{:03}", v / 1_000, v % 1_000),
+        TimePrecision::Seconds => v.to_string(),
+    };
+    match (value, hint) {
+        (Value::Unsigned(v), Some(DisplayHint::NoHint { zero_pad })) => {
+            format!("{v:0zero_pad$}")
+        }
+        (
+            Value::Unsigned(v),
+            Some(DisplayHint::Hexadecimal {
+                alternate,
+                uppercase,
+                zero_pad,
+            }),
+        ) => match (alternate, uppercase) {
+            (false, false) => format!("{v:0zero_pad$x}"),
+            (false, true) => format!("{v:0zero_pad$X}"),
+            (true, false) => format!("{v:#0zero_pad$x}"),
+            (true, true) => format!("{v:#0zero_pad$X}"),
+        },
+        (
+            Value::Unsigned(v),
+            Some(DisplayHint::Binary {
+                alternate,
+                zero_pad,
+            }),
+        ) => match alternate {
+            false => format!("{v:0zero_pad$b}"),
+            true => format!("{v:#0zero_pad$b}"),
+        },
+        (
+            Value::Unsigned(v),
+            Some(DisplayHint::Octal {
+                alternate,
+                zero_pad,
+            }),
+        ) => match alternate {
+            false => format!("{v:0zero_pad$o}"),
+            true => format!("{v:#0zero_pad$o}"),
+        },
+        (
+            Value::Unsigned(v),
+            Some(
+                DisplayHint::Seconds(precision)
+                | DisplayHint::Time(precision)
+                | DisplayHint::ISO8601(precision),
+            ),
+        ) => seconds(*v, precision),
+        (Value::Unsigned(v), _) => v.to_string(),
+        (Value::Signed(v), Some(DisplayHint::NoHint { zero_pad })) => format!("{v:0zero_pad$}"),
+        (Value::Signed(v), _) => v.to_string(),
+        (Value::Float(v), _) => v.to_string(),
+        (Value::Text(v), Some(DisplayHint::Debug)) => format!("{v:?}"),
+        (Value::Text(v), _) => v.clone(),
+        (Value::Bytes(v), Some(DisplayHint::Ascii)) => {
+            format!("b\"{}\"", v.escape_ascii())
+        }
+        (Value::Bytes(v), Some(DisplayHint::Hexadecimal { .. })) => {
+            let hex: Vec<_> = v.iter().map(|b| format!("{b:02x}")).collect();
+            format!("[{}]", hex.join(", "))
+        }
+        (Value::Bytes(v), _) => format!("{v:?}"),
+    }
+}
+
+struct Decoder<'t> {
+    table: &'t Table,
+}
+
+impl Decoder<'_> {
+    // The data of one value written with `format`, whose tag has already been read. `hint` comes
+    // from the enclosing parameter and applies where the nested string gives none.
+    fn format(
+        &self,
+        format: &Interned,
+        hint: Option<&DisplayHint>,
+        input: &mut Reader,
+    ) -> Result<String, String> {
+        if format.tag == "defmt_derived" && format.format.contains('|') {
+            let variants: Vec<_> = format.format.split('|').collect();
+            let variant = input.uint(1)? as usize;
+            let variant = variants
+                .get(variant)
+                .ok_or_else(|| format!("bad variant {variant} of {:?}", format.format))?;
+            return self.message(variant, hint, input);
+        }
+        self.message(&format.format, hint, input)
+    }
+
+    // A tag followed by the value's data.
+    fn tagged(&self, hint: Option<&DisplayHint>, input: &mut Reader) -> Result<String, String> {
+        let format = self.table.get(input.u16()?)?;
+        self.format(format, hint, input)
+    }
+
+    fn value(
+        &self,
+        ty: &Type,
+        hint: Option<&DisplayHint>,
+        input: &mut Reader,
+    ) -> Result<Value, String> {
+        Ok(match ty {
+            Type::Bool => Value::Text((input.uint(1)? != 0).to_string()),
+            Type::Char => Value::Text(
+                char::from_u32(input.uint(4)? as u32)
+                    .unwrap_or(char::REPLACEMENT_CHARACTER)
+                    .to_string(),
+            ),
+            Type::U8 => Value::Unsigned(input.uint(1)?),
+            Type::U16 => Value::Unsigned(input.uint(2)?),
+            Type::U32 | Type::Usize => Value::Unsigned(input.uint(4)?),
+            Type::U64 => Value::Unsigned(input.uint(8)?),
+            Type::U128 => Value::Unsigned(input.uint(16)?),
+            Type::I8 => Value::Signed(input.int(1)?),
+            Type::I16 => Value::Signed(input.int(2)?),
+            Type::I32 | Type::Isize => Value::Signed(input.int(4)?),
+            Type::I64 => Value::Signed(input.int(8)?),
+            Type::I128 => Value::Signed(input.int(16)?),
+            Type::F32 => Value::Float(f32::from_bits(input.uint(4)? as u32) as f64),
+            Type::F64 => Value::Float(f64::from_bits(input.uint(8)? as u64)),
+            Type::Str => {
+                let len = input.len()?;
+                Value::Text(String::from_utf8_lossy(input.bytes(len)?).into_owned())
+            }
+            Type::IStr => Value::Text(self.table.get(input.u16()?)?.format.clone()),
+            Type::U8Slice => {
+                let len = input.len()?;
+                Value::Bytes(input.bytes(len)?.to_vec())
+            }
+            Type::U8Array(len) => Value::Bytes(input.bytes(*len)?.to_vec()),
+            Type::Display | Type::Debug => Value::Text(input.formatted()?),
+            Type::Format => Value::Text(self.tagged(hint, input)?),
+            Type::FormatSequence => {
+                let mut text = String::new();
+                loop {
+                    match input.u16()? {
+                        0 => break,
+                        index => text += &self.format(self.table.get(index)?, hint, input)?,
+                    }
+                }
+                Value::Text(text)
+            }
+            Type::FormatSlice | Type::FormatArray(_) => {
+                let len = match ty {
+                    Type::FormatArray(len) => *len,
+                    _ => input.len()?,
+                };
+                let mut items = Vec::with_capacity(len);
+                if len > 0 {
+                    let format = self.table.get(input.u16()?)?;
+                    for _ in 0..len {
+                        items.push(self.format(format, hint, input)?);
+                    }
+                }
+                Value::Text(format!("[{}]", items.join(", ")))
+            }
+            Type::BitField(_) => return Err("bitfields are not supported".into()),
+        })
+    }
+
+    // Arguments are encoded once each, in index order, however often the string uses them.
+    fn message(
+        &self,
+        format: &str,
+        outer: Option<&DisplayHint>,
+        input: &mut Reader,
+    ) -> Result<String, String> {
+        let fragments = defmt_parser::parse(format, ParserMode::ForwardsCompatible)
+            .map_err(|e| format!("bad format string {format:?}: {e}"))?;
+        let mut params: Vec<_> = fragments
+            .iter()
+            .filter_map(|fragment| match fragment {
+                Fragment::Parameter(param) => Some(param),
+                Fragment::Literal(_) => None,
+            })
+            .collect();
+        params.sort_by_key(|param| param.index);
+        params.dedup_by_key(|param| param.index);
+        let mut values = HashMap::new();
+        for param in params {
+            let hint = param.hint.as_ref().or(outer);
+            values.insert(param.index, self.value(&param.ty, hint, input)?);
+        }
+        let mut text = String::new();
+        for fragment in &fragments {
+            match fragment {
+                Fragment::Literal(literal) => text += literal,
+                Fragment::Parameter(param) => {
+                    let hint = param.hint.as_ref().or(outer);
+                    text += &render(&values[&param.index], hint);
+                }
+            }
+        }
+        Ok(text)
+    }
+
+    fn frame(&self, frame: &[u8]) -> Result<String, String> {
+        let mut input = Reader(frame);
+        let format = self.table.get(input.u16()?)?;
+        let level = match format.tag.as_str() {
+            "defmt_trace" => "TRACE",
+            "defmt_debug" => "DEBUG",
+            "defmt_info" => "INFO",
+            "defmt_warn" => "WARN",
+            "defmt_error" => "ERROR",
+            _ => "",
+        };
+        let timestamp = self.table.get(self.table.timestamp)?;
+        let timestamp = self.message(&timestamp.format, None, &mut input)?;
+        let message = self.message(&format.format, None, &mut input)?;
+        Ok(format!("{timestamp} {level:<5} {message}"))
+    }
+}
+
+fn main() -> ExitCode {
+    let mut args = std::env::args().skip(1);
+    let Some(elf) = args.next() else {
+        eprintln!("usage: xapi-defmt <binary> [stream-file]");
+        return ExitCode::FAILURE;
+    };
+    let stream = args.next().unwrap_or_else(|| ".defmt_stream".into());
+    let (binary, stream) = match (std::fs::read(&elf), std::fs::read(&stream)) {
+        (Ok(binary), Ok(stream)) => (binary, stream),
+        (Err(e), _) => {
+            eprintln!("cannot read {elf}: {e}");
+            return ExitCode::FAILURE;
+        }
+        (_, Err(e)) => {
+            eprintln!("cannot read {stream}: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let mut input = Reader(&stream);
+    let header = input
+        .bytes(STREAM_MAGIC.len())
+        .and_then(|magic| Ok((magic, input.uint(8)? as u64, input.u16()?)));
+    let (anchor, timestamp) = match header {
+        Ok((magic, anchor, timestamp)) if magic == STREAM_MAGIC => (anchor, timestamp),
+        _ => {
+            eprintln!("not a defmt stream written by xapi_rs");
+            return ExitCode::FAILURE;
+        }
+    };
+    let table = match Table::load(&binary, anchor, timestamp) {
+        Ok(table) => table,
+        Err(e) => {
+            eprintln!("cannot load {elf}: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let decoder = Decoder { table: &table };
+    while !input.0.is_empty() {
+        let frame = match input.len().and_then(|len| input.bytes(len)) {
+            Ok(frame) => frame,
+            Err(_) => {
+                eprintln!("stream ends in the middle of a frame");
+                break;
+            }
+        };
+        match decoder.frame(frame) {
+            Ok(line) => println!("{line}"),
+            Err(e) => eprintln!("skipping frame: {e}"),
+        }
+    }
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,107 @@
+//! Resolves the program counters printed by `dump_backtrace` (or found in a saved crash record)
+//! against the firmware ELF, including inlined frames.
+//!
+//! ```text
+//! cargo run --target x86_64-unknown-linux-gnu --features symbolize --bin xapi-symbolize -- \
+//!     target/xtensa-esp32s3-none-elf/debug/xapi_rs [monitor.log]
+//! ```
+//!
+//! Reads stdin when no log file is given. Every line is echoed; each full-width `0x…` address
+//! that maps to code is followed by its frames, innermost first.
+use addr2line::Loader;
+use std::borrow::Cow;
+use std::io::{BufRead, BufReader, Read};
+use std::process::ExitCode;
+
+fn program_counters(line: &str) -> impl Iterator<Item = u64> + '_ {
+    line.match_indices("0x").filter_map(move |(at, _)| {
+        let digits = &line[at + 2..];
+        let end = digits
+            .find(|c: char| !c.is_ascii_hexdigit())
+            .unwrap_or(digits.len());
+        match end {
+            8..=16 => u64::from_str_radix(&digits[..end], 16).ok(),
+            _ => None,
+        }
+    })
+}
+
+fn location(file: Option<&str>, line: Option<u32>) -> String {
+    match (file, line) {
+        (Some(file), Some(line)) => format!("{file}:{line}"),
+        (Some(file), None) => format!("{file}:?"),
+        _ => "??:?".into(),
+    }
+}
+
+fn symbolize(loader: &Loader, pc: u64) -> Vec<String> {
+    let mut frames = Vec::new();
+    if let Ok(mut iter) = loader.find_frames(pc) {
+        while let Ok(Some(frame)) = iter.next() {
+            let function = frame
+                .function
+                .as_ref()
+                .and_then(|name| name.demangle().ok())
+                .map(Cow::into_owned)
+                .or_else(|| {
+                    loader
+                        .find_symbol(pc)
+                        .map(|name| addr2line::demangle_auto(name.into(), None).into_owned())
+                })
+                .unwrap_or_else(|| "??".into());
+            let at = frame
+                .location
+                .map(|loc| location(loc.file, loc.line))
+                .unwrap_or_else(|| location(None, None));
+            frames.push(format!("{function} at {at}"));
+        }
+    }
+    if frames.is_empty()
+        && let Some(name) = loader.find_symbol(pc)
+    {
+        frames.push(format!(
+            "{} at ??:?",
+            addr2line::demangle_auto(name.into(), None)
+        ));
+    }
+    frames
+}
+
+fn main() -> ExitCode {
+    let mut args = std::env::args().skip(1);
+    let Some(elf) = args.next() else {
+        eprintln!("usage: xapi-symbolize <firmware.elf> [log-file]");
+        return ExitCode::FAILURE;
+    };
+    let loader = match Loader::new(&elf) {
+        Ok(loader) => loader,
+        Err(e) => {
+            eprintln!("cannot load {elf}: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let input: Box<dyn Read> = match args.next() {
+        Some(path) => match std::fs::File::open(&path) {
+            Ok(file) => Box::new(file),
+            Err(e) => {
+                eprintln!("cannot open {path}: {e}");
+                return ExitCode::FAILURE;
+            }
+        },
+        None => Box::new(std::io::stdin()),
+    };
+    for line in BufReader::new(input).lines() {
+        let Ok(line) = line else { break };
+        println!("{line}");
+        for pc in program_counters(&line) {
+            for (depth, frame) in symbolize(&loader, pc).iter().enumerate() {
+                if depth == 0 {
+                    println!("    0x{pc:08x}: {frame}");
+                } else {
+                    println!("                 (inlined by) {frame}");
+                }
+            }
+        }
+    }
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,81 @@
+//! Logging macros for `osdep` and `netclients`. They expand to `defmt` with the `defmt` feature
+//! and to `log` otherwise, so format strings must suit both: positional arguments only, and
+//! values without a `defmt::Format` impl wrapped in [`Disp`] or [`Dbg`].
+#![allow(unused_macros)]
+
+#[cfg(feature = "defmt")]
+macro_rules! trace {
+    ($($arg:tt)+) => { ::defmt::trace!($($arg)+) };
+}
+#[cfg(not(feature = "defmt"))]
+macro_rules! trace {
+    ($($arg:tt)+) => { ::log::trace!($($arg)+) };
+}
+
+#[cfg(feature = "defmt")]
+macro_rules! debug {
+    ($($arg:tt)+) => { ::defmt::debug!($($arg)+) };
+}
+#[cfg(not(feature = "defmt"))]
+macro_rules! debug {
+    ($($arg:tt)+) => { ::log::debug!($($arg)+) };
+}
+
+#[cfg(feature = "defmt")]
+macro_rules! info {
+    ($($arg:tt)+) => { ::defmt::info!($($arg)+) };
+}
+#[cfg(not(feature = "defmt"))]
+macro_rules! info {
+    ($($arg:tt)+) => { ::log::info!($($arg)+) };
+}
+
+#[cfg(feature = "defmt")]
+macro_rules! warn {
+    ($($arg:tt)+) => { ::defmt::warn!($($arg)+) };
+}
+#[cfg(not(feature = "defmt"))]
+macro_rules! warn {
+    ($($arg:tt)+) => { ::log::warn!($($arg)+) };
+}
+
+#[cfg(feature = "defmt")]
+macro_rules! error {
+    ($($arg:tt)+) => { ::defmt::error!($($arg)+) };
+}
+#[cfg(not(feature = "defmt"))]
+macro_rules! error {
+    ($($arg:tt)+) => { ::log::error!($($arg)+) };
+}
+
+/// Logs a value through its `Display` impl. Under `defmt` it is formatted on the device.
+pub struct Disp<T>(pub T);
+
+/// Logs a value through its `Debug` impl. Under `defmt` it is formatted on the device.
+pub struct Dbg<T>(pub T);
+
+impl<T: core::fmt::Display> core::fmt::Display for Disp<T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl<T: core::fmt::Debug> core::fmt::Display for Dbg<T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl<T: core::fmt::Display> defmt::Format for Disp<T> {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(f, "{}", defmt::Display2Format(&self.0))
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl<T: core::fmt::Debug> defmt::Format for Dbg<T> {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(f, "{}", defmt::Debug2Format(&self.0))
+    }
+}
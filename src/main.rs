@@ -19,8 +19,11 @@ extern crate core;
 extern crate no_std_compat2 as std;
 
 use std::prelude::v1::*;
+#[macro_use]
+mod fmt;
 mod netclients;
 mod osdep;
+use crate::osdep::mem;
 use crate::osdep::startup::*;
 use crate::osdep::typedefs::{GlobalStatics, SpawnerStatics};
 use alloc::boxed::Box;
@@ -30,7 +33,9 @@ use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
 use embassy_sync::lazy_lock::LazyLock;
 use embassy_sync::signal::Signal;
 
-const LOG_LEVEL: log::LevelFilter = log::LevelFilter::Trace;
+// Also the compile-time `DEFMT_LOG` for the `defmt` feature; build.rs reads it from here. Directives
+// match whole path segments there, so name the crate in full.
+const LOG_FILTER: &str = "off,xapi_rs=trace,esp_hal::psram=trace";
 const SSID: &str = "slashdot2g";
 const PASSWORD: &str = "slashdot";
 
@@ -39,6 +44,10 @@ pub(crate) static NOTIFIER: LazyLock<Signal<CriticalSectionRawMutex, bool>> =
 
 #[task]
 async fn run_mqtt_bridge(statics: GlobalStatics, spawner: Spawner, spawner1: Spawner) {
+    mem::tagged("run_mqtt_bridge", mqtt_bridge(statics, spawner, spawner1)).await
+}
+
+async fn mqtt_bridge(statics: GlobalStatics, spawner: Spawner, spawner1: Spawner) {
     spawner.spawn(run_mqtt_bridge2(statics.clone(), spawner.clone(), spawner1.clone())).ok();
     spawner1.spawn(run_mqtt_bridge3(statics.clone(), spawner.clone(), spawner1.clone())).ok();
     log::info!("login to abcd");
@@ -1393,3 +1402,8 @@ fn main() -> ! {
         main_real()
     }
 }
+
+#[cfg(not(target_arch = "xtensa"))]
+fn main() {
+    main_real()
+}
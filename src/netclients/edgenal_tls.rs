@@ -1,9 +1,11 @@
-use crate::osdep::mem::PSRAM_ALLOCATOR;
+use crate::osdep::mem::SlabPool;
 use crate::osdep::net::*;
 use alloc::boxed::Box;
 use core::ffi::CStr;
 use core::fmt::{Debug, Display};
 use core::net::SocketAddr;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, Ordering};
 use edge_http::io::Error;
 use edge_nal::{Close, Readable, TcpConnect, TcpShutdown, TcpSplit};
 use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
@@ -13,12 +15,42 @@ use embedded_io_async::{Read, Write};
 static MUTEX: embassy_sync::mutex::Mutex<CriticalSectionRawMutex, bool> =
     embassy_sync::mutex::Mutex::new(false);
 
+const FUTURE_BLOCK: usize = 256;
+// Boxed socket futures, at most a read, a write and a close in flight per connection.
+static FUTURE_POOL: SlabPool = SlabPool::new("tcp_futures", FUTURE_BLOCK, TOTAL_CONNECTIONS * 3);
+static OVERSIZED: AtomicBool = AtomicBool::new(false);
+// The socket buffers of the two `TcpStack`s, one per core.
+#[cfg(all(target_arch = "xtensa", target_os = "none"))]
+static IO_POOL: SlabPool = SlabPool::new("tcp_io", size_of::<TcpBuffs>(), 2);
+
+/// Boxes a socket future in [`FUTURE_POOL`]. A future larger than a block still works, the pool
+/// hands it to `PSRAM_ALLOCATOR` and counts it in `fallbacks()`; the first one is logged so the
+/// block size can be raised.
+fn pooled<F: Future>(future: F) -> Pin<Box<F, &'static SlabPool>> {
+    if size_of::<F>() > FUTURE_BLOCK && !OVERSIZED.swap(true, Ordering::Relaxed) {
+        warn!(
+            "{} byte socket future does not fit the {} byte pool blocks",
+            size_of::<F>(),
+            FUTURE_BLOCK
+        );
+    }
+    Box::pin_in(future, &FUTURE_POOL)
+}
+
+/// Socket buffers for a [`TcpStack`], taken from the slab pool in PSRAM instead of static RAM.
+#[cfg(all(target_arch = "xtensa", target_os = "none"))]
+pub fn io_buffers() -> &'static TcpBuffs {
+    IO_POOL.init();
+    Box::leak(Box::new_in(TcpBuffs::new(), &IO_POOL))
+}
+
 pub enum TcpWrapper<'a> {
     Plain(&'a TcpStack),
 }
 
 impl<'a> TcpWrapper<'a> {
     pub fn plain(stack: &'a TcpStack) -> Self {
+        FUTURE_POOL.init();
         TcpWrapper::Plain(stack)
     }
 }
@@ -46,16 +78,14 @@ impl ErrorType for TcpSock<'_> {
 macro_rules! samesies {
     ($self:ident, $st:ident) => {
         match $self {
-            TcpSock::Plain(sock) => Box::pin_in(sock.$st(), &PSRAM_ALLOCATOR)
-                .await
-                .map_err(EdgeHttpError::from),
+            TcpSock::Plain(sock) => pooled(sock.$st()).await.map_err(EdgeHttpError::from),
         }
     };
 }
 macro_rules! samesies_arg {
     ($self:ident, $st:ident, $arg:expr) => {
         match $self {
-            TcpSock::Plain(sock) => Box::pin(sock.$st($arg)).await.map_err(EdgeHttpError::from),
+            TcpSock::Plain(sock) => pooled(sock.$st($arg)).await.map_err(EdgeHttpError::from),
         }
     };
 }
@@ -64,9 +94,7 @@ impl TcpShutdown for TcpSock<'_> {
     async fn close(&mut self, what: Close) -> Result<(), Self::Error> {
         // let _ = MUTEX.lock().await;
         match self {
-            TcpSock::Plain(plain) => Box::pin_in(plain.close(what), &PSRAM_ALLOCATOR)
-                .await
-                .map_err(From::from),
+            TcpSock::Plain(plain) => pooled(plain.close(what)).await.map_err(From::from),
         }
     }
 
@@ -76,13 +104,9 @@ impl TcpShutdown for TcpSock<'_> {
     }
 }
 
-#[cfg(not(any(all(target_arch = "xtensa", target_os = "none"), feature = "tracing")))]
+#[cfg(not(all(target_arch = "xtensa", target_os = "none")))]
 pub mod split {
-    use crate::netclients::edgenal_tls::{EdgeHttpError, TcpSock};
     use crate::osdep::net::TcpSocket;
-    use edge_nal::{Readable, TcpSplit};
-    use embedded_io::ErrorType;
-    use embedded_io_async::{Read, Write};
 
     pub struct TcpReaderWrapper<'a>(pub(crate) &'a TcpSocket);
     pub struct TcpWriterWrapper<'a>(pub(crate) &'a TcpSocket);
@@ -189,7 +213,7 @@ impl TcpConnect for TcpWrapper<'_> {
         // let _ = MUTEX.lock().await;
         match self {
             TcpWrapper::Plain(plain) => {
-                let sock = Box::pin_in(plain.connect(remote), &PSRAM_ALLOCATOR)
+                let sock = pooled(plain.connect(remote))
                     .await
                     .map_err(EdgeHttpError::from)?;
                 Ok(TcpSock::Plain(sock))
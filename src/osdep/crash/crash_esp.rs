@@ -0,0 +1,63 @@
+use super::record::{CrashReason, CrashRecord, ENCODED_LEN};
+use core::ptr::{addr_of, addr_of_mut};
+use core::sync::atomic::{AtomicBool, Ordering};
+use esp_backtrace::Backtrace;
+use esp_hal::trapframe::TrapFrame;
+use esp_hal::xtensa_lx_rt::exception::ExceptionCause;
+use esp_println::println;
+
+// RTC fast memory is left alone by a software reset, so the record is still there on the next boot.
+#[esp_hal::ram(unstable(rtc_fast, persistent))]
+static mut CRASH_SLOT: [u8; ENCODED_LEN] = [0; ENCODED_LEN];
+
+pub fn record_crash(record: &CrashRecord) {
+    unsafe { addr_of_mut!(CRASH_SLOT).write_volatile(record.encode()) };
+}
+
+/// Returns the record left by the previous run, if it crashed, and clears it.
+pub fn take_crash_record() -> Option<CrashRecord> {
+    let bytes = unsafe { addr_of!(CRASH_SLOT).read_volatile() };
+    unsafe { addr_of_mut!(CRASH_SLOT).write_volatile([0; ENCODED_LEN]) };
+    CrashRecord::decode(&bytes)
+}
+
+/// The panic handler below is always in place on the device.
+pub fn install_crash_handler() {}
+
+// Set by the exception entry below, so the panic it raises is recorded as an exception.
+static EXCEPTION: AtomicBool = AtomicBool::new(false);
+
+/// CPU exceptions end up here; it stands in for esp-hal's `exception-handler` feature.
+#[unsafe(no_mangle)]
+#[unsafe(link_section = ".rwtext")]
+unsafe fn __user_exception(cause: ExceptionCause, _context: &TrapFrame) {
+    EXCEPTION.store(true, Ordering::SeqCst);
+    panic!("CPU exception {:?}", cause);
+}
+
+/// Stores the crash record, prints the panic and resets the chip. The reset brings the firmware
+/// back up without a power cycle, and the next boot finds the record in [`CRASH_SLOT`].
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    let backtrace = Backtrace::capture();
+    let pcs = backtrace
+        .frames()
+        .into_iter()
+        .map(|frame| frame.program_counter() as u64);
+    let reason = if EXCEPTION.load(Ordering::SeqCst) {
+        CrashReason::Exception
+    } else {
+        CrashReason::Panic
+    };
+    let record = super::capture(reason, info.message(), info.location(), pcs);
+    record_crash(&record);
+    crate::osdep::logger::flush_logs();
+
+    println!("\n\n====================== PANIC ======================");
+    println!("{info}");
+    println!("\nBacktrace:\n");
+    for frame in backtrace.frames() {
+        println!("0x{:x}", frame.program_counter());
+    }
+    esp_hal::system::software_reset()
+}
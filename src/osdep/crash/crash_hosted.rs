@@ -0,0 +1,79 @@
+use super::record::{CrashReason, CrashRecord};
+use crate::fmt::Disp;
+use crate::osdep::mem::{TraceHeap, heap_free};
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+static PATH: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+/// Points the crash record somewhere else; by default `XAPI_CRASH_FILE`, then `./.crash_record`.
+pub fn set_crash_file(path: PathBuf) {
+    PATH.lock().unwrap().replace(path);
+}
+
+fn path() -> PathBuf {
+    PATH.lock()
+        .unwrap()
+        .get_or_insert_with(|| {
+            std::env::var_os("XAPI_CRASH_FILE")
+                .map(PathBuf::from)
+                .unwrap_or_else(|| ".crash_record".into())
+        })
+        .clone()
+}
+
+pub fn record_crash(record: &CrashRecord) {
+    if let Err(e) = fs::write(path(), record.encode()) {
+        warn!("cannot write crash record: {}", Disp(e));
+    }
+}
+
+/// Returns the record left by the previous run, if it crashed, and clears it.
+pub fn take_crash_record() -> Option<CrashRecord> {
+    let path = path();
+    let bytes = fs::read(&path).ok()?;
+    let _ = fs::remove_file(&path);
+    CrashRecord::decode(&bytes)
+}
+
+/// Records every panic before handing it to the previous hook.
+pub fn install_crash_handler() {
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let mut frames = Vec::new();
+        backtrace::trace(|frame| {
+            frames.push(frame.ip() as u64);
+            frames.len() < 128
+        });
+        // Start at the caller of the panic machinery.
+        let panicking = frames.iter().rposition(|ip| {
+            let mut inside = false;
+            backtrace::resolve(*ip as *mut _, |symbol| {
+                inside |= symbol
+                    .name()
+                    .and_then(|name| name.as_str())
+                    .is_some_and(|name| name.contains("panicking"));
+            });
+            inside
+        });
+        let message = info
+            .payload()
+            .downcast_ref::<&str>()
+            .copied()
+            .or_else(|| info.payload().downcast_ref::<String>().map(String::as_str))
+            .unwrap_or("Box<dyn Any>");
+        let pcs = frames.into_iter().skip(panicking.map_or(0, |at| at + 1));
+        let mut record = super::capture(CrashReason::Panic, message, info.location(), pcs);
+        // The hosted heaps are only counters, reading them cannot block.
+        let free = |heap| Some(heap_free(heap).min(u32::MAX as usize) as u32);
+        record.internal_free = free(TraceHeap::Internal);
+        record.psram_free = free(TraceHeap::Psram);
+        record_crash(&record);
+        crate::osdep::logger::flush_logs();
+        previous(info);
+    }));
+}
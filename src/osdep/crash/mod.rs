@@ -0,0 +1,39 @@
+#[cfg_attr(not(all(target_arch = "xtensa")), path = "crash_hosted.rs")]
+#[cfg_attr(all(target_arch = "xtensa", target_os = "none"), path = "crash_esp.rs")]
+#[cfg_attr(
+    all(target_arch = "xtensa", target_os = "espidf"),
+    path = "crash_idf.rs"
+)]
+mod crash_inner;
+mod record;
+pub use crash_inner::*;
+pub use record::{CrashReason, CrashRecord, MAX_MESSAGE, MAX_PCS};
+
+use crate::osdep::mem::{TraceHeap, last_free};
+use core::fmt::{Display, Write};
+use core::panic::Location;
+
+/// Builds the record for a panic; must not allocate, it runs inside the panic handler. The heaps'
+/// locks may be held by the code that panicked, so the free figures are the last ones sampled by
+/// `pressure_task`; callers that can read the heaps safely may overwrite them.
+pub(crate) fn capture(
+    reason: CrashReason,
+    message: impl Display,
+    location: Option<&Location<'_>>,
+    pcs: impl IntoIterator<Item = u64>,
+) -> CrashRecord {
+    let mut record = CrashRecord::new(reason, crate::osdep::time::epoch_ns());
+    let _ = write!(record, "{message}");
+    if let Some(location) = location {
+        let _ = write!(record, " at {}:{}", location.file(), location.line());
+    }
+    let free = |heap| last_free(heap).map(|free| free.min(u32::MAX as usize) as u32);
+    record.internal_free = free(TraceHeap::Internal);
+    record.psram_free = free(TraceHeap::Psram);
+    for pc in pcs {
+        if !record.push_pc(pc) {
+            break;
+        }
+    }
+    record
+}
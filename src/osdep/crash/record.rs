@@ -0,0 +1,221 @@
+use core::fmt::{self, Display, Formatter};
+
+pub const MAX_PCS: usize = 16;
+pub const MAX_MESSAGE: usize = 128;
+pub(crate) const ENCODED_LEN: usize = 4 + 4 + 8 + 8 + MAX_PCS * 8 + MAX_MESSAGE + 4;
+const MAGIC: u32 = u32::from_le_bytes(*b"XCR1");
+// Stored in place of a free-heap figure that was not captured.
+const UNKNOWN: u32 = u32::MAX;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum CrashReason {
+    Panic,
+    /// A CPU exception, reported through the panic handler.
+    Exception,
+}
+
+/// Compact description of a crash, small enough to survive a reset in reserved RAM.
+#[derive(Clone, Copy)]
+pub struct CrashRecord {
+    pub reason: CrashReason,
+    pub uptime_ns: u64,
+    /// Free heap last sampled before the crash; `None` if it never was.
+    pub internal_free: Option<u32>,
+    pub psram_free: Option<u32>,
+    pcs: [u64; MAX_PCS],
+    pc_count: usize,
+    message: [u8; MAX_MESSAGE],
+    message_len: usize,
+}
+
+impl CrashRecord {
+    pub fn new(reason: CrashReason, uptime_ns: u64) -> Self {
+        Self {
+            reason,
+            uptime_ns,
+            internal_free: None,
+            psram_free: None,
+            pcs: [0; MAX_PCS],
+            pc_count: 0,
+            message: [0; MAX_MESSAGE],
+            message_len: 0,
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        core::str::from_utf8(&self.message[..self.message_len]).unwrap_or("<invalid utf-8>")
+    }
+
+    pub fn pcs(&self) -> &[u64] {
+        &self.pcs[..self.pc_count]
+    }
+
+    /// Returns false once the record is full; later frames are dropped.
+    pub fn push_pc(&mut self, pc: u64) -> bool {
+        if self.pc_count == MAX_PCS {
+            return false;
+        }
+        self.pcs[self.pc_count] = pc;
+        self.pc_count += 1;
+        true
+    }
+
+    pub(crate) fn encode(&self) -> [u8; ENCODED_LEN] {
+        let mut out = [0u8; ENCODED_LEN];
+        out[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+        out[4] = self.reason as u8;
+        out[5] = self.pc_count as u8;
+        out[6] = self.message_len as u8;
+        out[8..16].copy_from_slice(&self.uptime_ns.to_le_bytes());
+        out[16..20].copy_from_slice(&self.internal_free.unwrap_or(UNKNOWN).to_le_bytes());
+        out[20..24].copy_from_slice(&self.psram_free.unwrap_or(UNKNOWN).to_le_bytes());
+        for (pc, slot) in self.pcs.iter().zip(out[24..].chunks_exact_mut(8)) {
+            slot.copy_from_slice(&pc.to_le_bytes());
+        }
+        let message = 24 + MAX_PCS * 8;
+        out[message..message + MAX_MESSAGE].copy_from_slice(&self.message);
+        let sum = checksum(&out[..ENCODED_LEN - 4]);
+        out[ENCODED_LEN - 4..].copy_from_slice(&sum.to_le_bytes());
+        out
+    }
+
+    pub(crate) fn decode(bytes: &[u8]) -> Option<Self> {
+        let bytes: &[u8; ENCODED_LEN] = bytes.try_into().ok()?;
+        let word = |at: usize| u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap());
+        if word(0) != MAGIC || word(ENCODED_LEN - 4) != checksum(&bytes[..ENCODED_LEN - 4]) {
+            return None;
+        }
+        let reason = match bytes[4] {
+            0 => CrashReason::Panic,
+            1 => CrashReason::Exception,
+            _ => return None,
+        };
+        let mut record =
+            CrashRecord::new(reason, u64::from_le_bytes(bytes[8..16].try_into().unwrap()));
+        record.internal_free = Some(word(16)).filter(|free| *free != UNKNOWN);
+        record.psram_free = Some(word(20)).filter(|free| *free != UNKNOWN);
+        record.pc_count = (bytes[5] as usize).min(MAX_PCS);
+        for (pc, slot) in record.pcs.iter_mut().zip(bytes[24..].chunks_exact(8)) {
+            *pc = u64::from_le_bytes(slot.try_into().unwrap());
+        }
+        let message = 24 + MAX_PCS * 8;
+        record
+            .message
+            .copy_from_slice(&bytes[message..message + MAX_MESSAGE]);
+        record.message_len = (bytes[6] as usize).min(MAX_MESSAGE);
+        Some(record)
+    }
+}
+
+// FNV-1a; only has to tell a written record from leftover RAM contents.
+fn checksum(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c_9dc5, |hash, byte| {
+        (hash ^ *byte as u32).wrapping_mul(0x0100_0193)
+    })
+}
+
+/// Appends to the message, truncating at a character boundary once it is full.
+impl fmt::Write for CrashRecord {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let room = MAX_MESSAGE - self.message_len;
+        let mut take = s.len().min(room);
+        while !s.is_char_boundary(take) {
+            take -= 1;
+        }
+        self.message[self.message_len..self.message_len + take]
+            .copy_from_slice(&s.as_bytes()[..take]);
+        self.message_len += take;
+        Ok(())
+    }
+}
+
+/// Multi-line report; the `pc=` lines can be fed to `xapi-symbolize` as they are.
+impl Display for CrashRecord {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "crash reason={:?} uptime_ns={} internal_free={} psram_free={}",
+            self.reason,
+            self.uptime_ns,
+            Free(self.internal_free),
+            Free(self.psram_free)
+        )?;
+        write!(f, "message: {}", self.message())?;
+        for pc in self.pcs() {
+            write!(f, "\npc={pc:#010x}")?;
+        }
+        Ok(())
+    }
+}
+
+struct Free(Option<u32>);
+
+impl Display for Free {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(free) => write!(f, "{free}"),
+            None => f.write_str("-"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+    use core::fmt::Write;
+
+    fn sample() -> CrashRecord {
+        let mut record = CrashRecord::new(CrashReason::Exception, 1_234_567_890);
+        record.internal_free = Some(40_000);
+        record.psram_free = None;
+        let _ = write!(record, "boom at src/main.rs:{}", 42);
+        for pc in [0x4200_1000, 0x4200_2000, 0x4037_0000] {
+            assert!(record.push_pc(pc));
+        }
+        record
+    }
+
+    #[test]
+    fn round_trips_through_encode() {
+        let record = sample();
+        let decoded = CrashRecord::decode(&record.encode()).unwrap();
+        assert_eq!(decoded.reason, CrashReason::Exception);
+        assert_eq!(decoded.uptime_ns, 1_234_567_890);
+        assert_eq!(decoded.internal_free, Some(40_000));
+        assert_eq!(decoded.psram_free, None);
+        assert_eq!(decoded.message(), "boom at src/main.rs:42");
+        assert_eq!(decoded.pcs(), record.pcs());
+        assert_eq!(decoded.to_string(), record.to_string());
+    }
+
+    #[test]
+    fn rejects_truncated_or_damaged_input() {
+        let bytes = sample().encode();
+        assert!(CrashRecord::decode(&[]).is_none());
+        assert!(CrashRecord::decode(&bytes[..ENCODED_LEN - 1]).is_none());
+        assert!(CrashRecord::decode(&bytes[..ENCODED_LEN / 2]).is_none());
+        let mut damaged = bytes;
+        damaged[30] ^= 0xff;
+        assert!(CrashRecord::decode(&damaged).is_none());
+        assert!(CrashRecord::decode(&[0; ENCODED_LEN]).is_none());
+    }
+
+    #[test]
+    fn truncates_message_and_pcs() {
+        let mut record = CrashRecord::new(CrashReason::Panic, 0);
+        for _ in 0..MAX_MESSAGE {
+            let _ = record.write_str("é");
+        }
+        assert!(record.message().len() <= MAX_MESSAGE);
+        assert!(record.message().chars().all(|c| c == 'é'));
+        for pc in 0..MAX_PCS as u64 {
+            assert!(record.push_pc(pc));
+        }
+        assert!(!record.push_pc(99));
+        let decoded = CrashRecord::decode(&record.encode()).unwrap();
+        assert_eq!(decoded.message(), record.message());
+        assert_eq!(decoded.pcs().len(), MAX_PCS);
+    }
+}
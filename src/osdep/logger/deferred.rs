@@ -0,0 +1,194 @@
+use super::format::write_record;
+use super::record::LogRecord;
+use crate::osdep::time::{delay_ns_async, yield_now};
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicU8, AtomicU32, AtomicUsize, Ordering};
+use core::time::Duration;
+use embassy_executor::task;
+use log::Level;
+
+const QUEUE_DEPTH: usize = 32;
+// Records written per wake-up, so a burst cannot hog the executor.
+const DRAIN_BATCH: usize = 8;
+const DRAIN_INTERVAL: Duration = Duration::from_millis(10);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum LogMode {
+    /// Format and write on the calling core.
+    Inline,
+    /// Copy into a queue that [`log_drain`] writes out.
+    Deferred,
+}
+
+static MODE: AtomicU8 = AtomicU8::new(LogMode::Inline as u8);
+static DROPPED: AtomicU32 = AtomicU32::new(0);
+
+/// [`LogMode::Inline`] until changed here; deferred records need [`log_drain`] running.
+pub fn set_log_mode(mode: LogMode) {
+    MODE.store(mode as u8, Ordering::SeqCst);
+}
+
+pub fn log_mode() -> LogMode {
+    match MODE.load(Ordering::Relaxed) {
+        0 => LogMode::Inline,
+        _ => LogMode::Deferred,
+    }
+}
+
+/// Records lost because the queue was full.
+pub fn dropped_records() -> u32 {
+    DROPPED.load(Ordering::Relaxed)
+}
+
+struct Slot {
+    // `pos` when free for the producer at `pos`, `pos + 1` once written.
+    seq: AtomicUsize,
+    record: UnsafeCell<MaybeUninit<LogRecord>>,
+}
+
+// Bounded lock-free MPMC queue (Vyukov). Several cores log into it; the drain task and the panic
+// flush may both take records out.
+struct Queue {
+    slots: [Slot; QUEUE_DEPTH],
+    head: AtomicUsize,
+    tail: AtomicUsize,
+}
+
+unsafe impl Sync for Queue {}
+
+impl Queue {
+    const fn new() -> Self {
+        let mut slots = [const {
+            Slot {
+                seq: AtomicUsize::new(0),
+                record: UnsafeCell::new(MaybeUninit::uninit()),
+            }
+        }; QUEUE_DEPTH];
+        let mut i = 0;
+        while i < QUEUE_DEPTH {
+            slots[i].seq = AtomicUsize::new(i);
+            i += 1;
+        }
+        Queue {
+            slots,
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+
+    fn push(&self, record: &LogRecord) -> bool {
+        let mut pos = self.head.load(Ordering::Relaxed);
+        loop {
+            let slot = &self.slots[pos % QUEUE_DEPTH];
+            let lag = slot.seq.load(Ordering::Acquire).wrapping_sub(pos) as isize;
+            if lag == 0 {
+                match self.head.compare_exchange_weak(
+                    pos,
+                    pos.wrapping_add(1),
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        unsafe { (*slot.record.get()).write(*record) };
+                        slot.seq.store(pos.wrapping_add(1), Ordering::Release);
+                        return true;
+                    }
+                    Err(current) => pos = current,
+                }
+            } else if lag < 0 {
+                return false;
+            } else {
+                pos = self.head.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    fn pop(&self) -> Option<LogRecord> {
+        let mut pos = self.tail.load(Ordering::Relaxed);
+        loop {
+            let slot = &self.slots[pos % QUEUE_DEPTH];
+            let lag = slot
+                .seq
+                .load(Ordering::Acquire)
+                .wrapping_sub(pos.wrapping_add(1)) as isize;
+            if lag == 0 {
+                match self.tail.compare_exchange_weak(
+                    pos,
+                    pos.wrapping_add(1),
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        let record = unsafe { (*slot.record.get()).assume_init_read() };
+                        slot.seq
+                            .store(pos.wrapping_add(QUEUE_DEPTH), Ordering::Release);
+                        return Some(record);
+                    }
+                    Err(current) => pos = current,
+                }
+            } else if lag < 0 {
+                return None;
+            } else {
+                pos = self.tail.load(Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+static QUEUE: Queue = Queue::new();
+
+/// Queues `record` without blocking; counts it as dropped when the queue is full.
+pub(crate) fn defer(record: &LogRecord) {
+    if !QUEUE.push(record) {
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Writes out everything still queued, on the calling core. Safe to call from a panic handler.
+pub fn flush_logs() {
+    while let Some(record) = QUEUE.pop() {
+        write_record(&record);
+    }
+}
+
+/// Writes queued records out in the background; they only queue up after
+/// `set_log_mode(LogMode::Deferred)`.
+///
+/// It shares the core-0 executor with networking rather than getting one of its own: thread-mode
+/// executors have no priorities to give it, and an interrupt executor would run it above
+/// everything else. Instead it gives way after `DRAIN_BATCH` records and sleeps when the queue is
+/// empty, so a burst holds other tasks back by one batch of console writes at most.
+#[task]
+pub async fn log_drain() {
+    let mut reported = 0;
+    loop {
+        let mut written = 0;
+        while written < DRAIN_BATCH
+            && let Some(record) = QUEUE.pop()
+        {
+            write_record(&record);
+            written += 1;
+        }
+        let dropped = dropped_records();
+        if dropped != reported {
+            let mut note = LogRecord::new(
+                Level::Warn,
+                crate::osdep::mem::current_core(),
+                module_path!(),
+            );
+            let _ = core::fmt::write(
+                &mut note,
+                format_args!("{} log records dropped", dropped.wrapping_sub(reported)),
+            );
+            write_record(&note);
+            reported = dropped;
+        }
+        if written < DRAIN_BATCH {
+            delay_ns_async(DRAIN_INTERVAL).await;
+        } else {
+            yield_now().await;
+        }
+    }
+}
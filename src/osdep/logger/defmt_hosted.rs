@@ -0,0 +1,83 @@
+//! `defmt` global logger for hosted builds. Frames are appended to `XAPI_DEFMT_FILE`, by default
+//! `./.defmt_stream`, and turned back into text by the `xapi-defmt` tool.
+//!
+//! The stream starts with [`STREAM_MAGIC`], the run-time address of [`XAPI_DEFMT_ANCHOR`] as a
+//! little-endian `u64` and the `u16` index of the timestamp's format string. Each frame follows
+//! as a little-endian `u32` length and the raw (unencoded) defmt bytes.
+use alloc::vec::Vec;
+use core::cell::{Cell, RefCell};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::{LazyLock, Mutex, PoisonError};
+
+const STREAM_MAGIC: &[u8; 4] = b"XDF1";
+
+/// defmt's string indices are addresses, which move with ASLR. The decoder compares this
+/// symbol's address in the ELF with the one in the stream header to undo the shift.
+#[unsafe(no_mangle)]
+static XAPI_DEFMT_ANCHOR: u8 = 0;
+
+unsafe extern "Rust" {
+    // Defined by `defmt::timestamp!` and pointing at its format string. Only defmt's linker script
+    // keeps that string otherwise, so hosted links need this reference.
+    static __DEFMT_MARKER_TIMESTAMP_WAS_DEFINED: &'static u8;
+}
+
+thread_local! {
+    static FRAME: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+    static TAKEN: Cell<bool> = const { Cell::new(false) };
+}
+
+static STREAM: LazyLock<Option<Mutex<BufWriter<File>>>> = LazyLock::new(|| {
+    let path = std::env::var_os("XAPI_DEFMT_FILE")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| ".defmt_stream".into());
+    let mut out = BufWriter::new(File::create(path).ok()?);
+    out.write_all(STREAM_MAGIC).ok()?;
+    let anchor = &raw const XAPI_DEFMT_ANCHOR as u64;
+    out.write_all(&anchor.to_le_bytes()).ok()?;
+    let timestamp = unsafe { __DEFMT_MARKER_TIMESTAMP_WAS_DEFINED } as *const u8 as u16;
+    out.write_all(&timestamp.to_le_bytes()).ok()?;
+    Some(Mutex::new(out))
+});
+
+#[defmt::global_logger]
+struct StreamLogger;
+
+unsafe impl defmt::Logger for StreamLogger {
+    fn acquire() {
+        // A log from inside a `Format` impl would clear the frame being written, as in defmt-rtt.
+        if TAKEN.replace(true) {
+            panic!("defmt logger taken reentrantly");
+        }
+        FRAME.with_borrow_mut(Vec::clear);
+    }
+
+    unsafe fn flush() {
+        if let Some(stream) = STREAM.as_ref() {
+            let _ = stream
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .flush();
+        }
+    }
+
+    unsafe fn release() {
+        TAKEN.set(false);
+        let Some(stream) = STREAM.as_ref() else {
+            return;
+        };
+        FRAME.with_borrow(|frame| {
+            let mut stream = stream.lock().unwrap_or_else(PoisonError::into_inner);
+            let _ = stream.write_all(&(frame.len() as u32).to_le_bytes());
+            let _ = stream.write_all(frame);
+            // Keep the file readable while the firmware runs.
+            let _ = stream.flush();
+        });
+    }
+
+    unsafe fn write(bytes: &[u8]) {
+        FRAME.with_borrow_mut(|frame| frame.extend_from_slice(bytes));
+    }
+}
@@ -0,0 +1,174 @@
+use crate::fmt::Disp;
+use crate::osdep::storage::kv_store::{get_key, put_key};
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use core::fmt::{Display, Formatter};
+use embassy_executor::task;
+use embassy_sync::blocking_mutex::CriticalSectionMutex;
+use log::{Level, LevelFilter};
+
+const FILTER_KEY: &str = "log_filter";
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct FilterError(pub String);
+
+impl Display for FilterError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "invalid log directive `{}`", self.0)
+    }
+}
+
+struct Directive {
+    module: String,
+    level: LevelFilter,
+}
+
+struct Filter {
+    spec: String,
+    default: LevelFilter,
+    // Longest module first, so the first match is the most specific one.
+    directives: Vec<Directive>,
+}
+
+impl Filter {
+    fn parse(spec: &str) -> Result<Filter, FilterError> {
+        let mut default = LevelFilter::Off;
+        let mut directives = Vec::new();
+        for part in spec
+            .split(',')
+            .map(str::trim)
+            .filter(|part| !part.is_empty())
+        {
+            let invalid = || FilterError(part.to_string());
+            match part.split_once('=') {
+                Some((module, level)) => directives.push(Directive {
+                    module: module.trim().to_string(),
+                    level: level.trim().parse().map_err(|_| invalid())?,
+                }),
+                None => match part.parse() {
+                    Ok(level) => default = level,
+                    // A bare module name enables everything below it, as in env_logger.
+                    Err(_) => directives.push(Directive {
+                        module: part.to_string(),
+                        level: LevelFilter::Trace,
+                    }),
+                },
+            }
+        }
+        directives.sort_by(|a, b| b.module.len().cmp(&a.module.len()));
+        Ok(Filter {
+            spec: spec.to_string(),
+            default,
+            directives,
+        })
+    }
+
+    fn level_for(&self, module: &str) -> LevelFilter {
+        self.directives
+            .iter()
+            .find(|directive| module.starts_with(directive.module.as_str()))
+            .map_or(self.default, |directive| directive.level)
+    }
+
+    fn max_level(&self) -> LevelFilter {
+        self.directives
+            .iter()
+            .map(|directive| directive.level)
+            .fold(self.default, Ord::max)
+    }
+}
+
+static FILTER: CriticalSectionMutex<RefCell<Option<Filter>>> =
+    CriticalSectionMutex::new(RefCell::new(None));
+
+/// Replaces the active filter with an env_logger-style directive string such as
+/// `info,xapi=debug,esp_radio::wifi::os_adapter=off`. The longest matching module prefix wins.
+pub fn set_log_filter(spec: &str) -> Result<(), FilterError> {
+    let filter = Filter::parse(spec)?;
+    log::set_max_level(filter.max_level());
+    FILTER.lock(|current| current.replace(Some(filter)));
+    Ok(())
+}
+
+pub fn log_filter() -> String {
+    FILTER.lock(|filter| {
+        filter
+            .borrow()
+            .as_ref()
+            .map(|filter| filter.spec.clone())
+            .unwrap_or_default()
+    })
+}
+
+pub fn level_for(module: &str) -> LevelFilter {
+    FILTER.lock(|filter| {
+        filter
+            .borrow()
+            .as_ref()
+            .map_or(LevelFilter::Off, |filter| filter.level_for(module))
+    })
+}
+
+pub fn log_enabled(level: Level, module: &str) -> bool {
+    level <= level_for(module)
+}
+
+/// Applies `spec` and keeps it in `kv_store` for the next boot.
+pub async fn save_log_filter(spec: &str) -> Result<(), FilterError> {
+    set_log_filter(spec)?;
+    put_key(FILTER_KEY, spec).await;
+    Ok(())
+}
+
+/// Applies the filter saved by [`save_log_filter`], if there is one. On hosted builds a filter in
+/// `XAPI_LOG` replaces it and is saved in its place.
+#[task]
+pub async fn restore_log_filter() {
+    #[cfg(not(target_arch = "xtensa"))]
+    if let Ok(spec) = std::env::var("XAPI_LOG") {
+        if let Err(e) = save_log_filter(&spec).await {
+            warn!("ignoring XAPI_LOG: {}", Disp(e));
+        }
+        return;
+    }
+    if let Some(spec) = get_key(FILTER_KEY).await
+        && let Err(e) = set_log_filter(&spec)
+    {
+        warn!("ignoring saved log filter: {}", Disp(e));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn longest_module_prefix_wins() {
+        let filter = Filter::parse("warn,xapi=debug,xapi::osdep::net=off, esp_hal::psram").unwrap();
+        assert_eq!(filter.level_for("xapi::main"), LevelFilter::Debug);
+        assert_eq!(filter.level_for("xapi::osdep::net::tls"), LevelFilter::Off);
+        assert_eq!(filter.level_for("esp_hal::psram"), LevelFilter::Trace);
+        assert_eq!(filter.level_for("esp_hal::gpio"), LevelFilter::Warn);
+        assert_eq!(filter.max_level(), LevelFilter::Trace);
+    }
+
+    #[test]
+    fn later_default_replaces_earlier_one() {
+        let filter = Filter::parse("info,,error").unwrap();
+        assert_eq!(filter.level_for("anything"), LevelFilter::Error);
+        assert_eq!(
+            Filter::parse("").unwrap().level_for("xapi"),
+            LevelFilter::Off
+        );
+    }
+
+    #[test]
+    fn bad_level_is_rejected() {
+        assert_eq!(
+            Filter::parse("info,xapi=loud").err(),
+            Some(FilterError("xapi=loud".to_string()))
+        );
+        assert!(Filter::parse("xapi=").is_err());
+    }
+}
@@ -0,0 +1,124 @@
+use super::record::LogRecord;
+use super::sink;
+use crate::osdep::time::{WallTime, wall_clock_at};
+use core::fmt::{self, Display, Formatter, Write};
+use core::sync::atomic::{AtomicU8, Ordering};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum LogFormat {
+    /// `12.345 0/boot INFO  xapi_rs::net: message`, after the wall clock when known.
+    Compact,
+    /// Wall clock when known, nanosecond uptime, and the full set of fields.
+    Verbose,
+    /// One JSON object per line.
+    Json,
+}
+
+static FORMAT: AtomicU8 = AtomicU8::new(LogFormat::Compact as u8);
+
+pub fn set_log_format(format: LogFormat) {
+    FORMAT.store(format as u8, Ordering::SeqCst);
+}
+
+pub fn log_format() -> LogFormat {
+    match FORMAT.load(Ordering::Relaxed) {
+        0 => LogFormat::Compact,
+        1 => LogFormat::Verbose,
+        _ => LogFormat::Json,
+    }
+}
+
+/// A record laid out in the current [`LogFormat`]. `message` may be longer than the copy kept in
+/// the record.
+pub(crate) struct Line<'a> {
+    pub record: &'a LogRecord,
+    pub message: &'a dyn Display,
+}
+
+// Escapes whatever is written through it as the inside of a JSON string.
+struct JsonEscaped<'a, 'b>(&'a mut Formatter<'b>);
+
+impl Write for JsonEscaped<'_, '_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            match c {
+                '"' => self.0.write_str("\\\"")?,
+                '\\' => self.0.write_str("\\\\")?,
+                '\n' => self.0.write_str("\\n")?,
+                '\r' => self.0.write_str("\\r")?,
+                '\t' => self.0.write_str("\\t")?,
+                c if (c as u32) < 0x20 => write!(self.0, "\\u{:04x}", c as u32)?,
+                c => self.0.write_char(c)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Display for Line<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let r = self.record;
+        let wall = wall_clock_at(r.timestamp_ns).map(WallTime);
+        let (secs, nanos) = (
+            r.timestamp_ns / 1_000_000_000,
+            r.timestamp_ns % 1_000_000_000,
+        );
+        match log_format() {
+            LogFormat::Compact => {
+                if let Some(wall) = wall {
+                    write!(f, "{wall} ")?;
+                }
+                write!(
+                    f,
+                    "{secs}.{:03} {}/{} {:<5} {}: {}",
+                    nanos / 1_000_000,
+                    r.core,
+                    r.task,
+                    r.level,
+                    r.module(),
+                    self.message
+                )
+            }
+            LogFormat::Verbose => {
+                if let Some(wall) = wall {
+                    write!(f, "{wall} ")?;
+                }
+                write!(
+                    f,
+                    "{secs}.{nanos:09} cpu={} task={} {}: {} - {}",
+                    r.core,
+                    r.task,
+                    r.module(),
+                    r.level,
+                    self.message
+                )
+            }
+            LogFormat::Json => {
+                write!(f, "{{\"ts_ns\":{}", r.timestamp_ns)?;
+                if let Some(wall) = wall {
+                    write!(f, ",\"wall\":\"{wall}\"")?;
+                }
+                write!(
+                    f,
+                    ",\"level\":\"{}\",\"core\":{},\"task\":\"",
+                    r.level, r.core
+                )?;
+                write!(JsonEscaped(f), "{}", r.task)?;
+                f.write_str("\",\"module\":\"")?;
+                JsonEscaped(f).write_str(r.module())?;
+                f.write_str("\",\"msg\":\"")?;
+                write!(JsonEscaped(f), "{}", self.message)?;
+                f.write_str("\"}")
+            }
+        }
+    }
+}
+
+/// Writes out a queued or stored record.
+pub(crate) fn write_record(record: &LogRecord) {
+    sink::write_line(&Line {
+        record,
+        message: &record.message(),
+    });
+}
@@ -0,0 +1,95 @@
+use super::record::LogRecord;
+use crate::osdep::mem::TraceHeap;
+use crate::osdep::typedefs::{PsramVec, vec_with_capacity};
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use core::sync::atomic::{AtomicBool, Ordering};
+use embassy_sync::blocking_mutex::CriticalSectionMutex;
+
+pub const DEFAULT_HISTORY: usize = 128;
+
+enum Storage {
+    Internal(Vec<LogRecord>),
+    Psram(PsramVec<LogRecord>),
+}
+
+struct History {
+    records: Storage,
+    capacity: usize,
+    // Oldest record once the buffer has wrapped.
+    head: usize,
+}
+
+impl History {
+    fn push(&mut self, record: &LogRecord) {
+        let (records, len) = match &mut self.records {
+            Storage::Internal(records) if records.len() < self.capacity => {
+                return records.push(*record);
+            }
+            Storage::Psram(records) if records.len() < self.capacity => {
+                return records.push(*record);
+            }
+            Storage::Internal(records) => (records.as_mut_slice(), self.capacity),
+            Storage::Psram(records) => (records.as_mut_slice(), self.capacity),
+        };
+        records[self.head] = *record;
+        self.head = (self.head + 1) % len;
+    }
+
+    fn records(&self) -> &[LogRecord] {
+        match &self.records {
+            Storage::Internal(records) => records,
+            Storage::Psram(records) => records,
+        }
+    }
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static HISTORY: CriticalSectionMutex<RefCell<Option<History>>> =
+    CriticalSectionMutex::new(RefCell::new(None));
+
+/// Keeps the last `capacity` records that pass the filter, in internal RAM or PSRAM.
+/// A capacity of 0 turns the history off. Earlier lines are discarded either way.
+pub fn enable_log_history(capacity: usize, heap: TraceHeap) {
+    let history = (capacity > 0).then(|| History {
+        records: match heap {
+            TraceHeap::Psram => Storage::Psram(vec_with_capacity(capacity)),
+            _ => Storage::Internal(Vec::with_capacity(capacity)),
+        },
+        capacity,
+        head: 0,
+    });
+    ENABLED.store(history.is_some(), Ordering::SeqCst);
+    // Freed outside the lock.
+    let _previous = HISTORY.lock(|current| current.replace(history));
+}
+
+pub(crate) fn history_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+pub(crate) fn remember(record: &LogRecord) {
+    HISTORY.lock(|history| {
+        if let Some(history) = history.borrow_mut().as_mut() {
+            history.push(record);
+        }
+    });
+}
+
+/// Up to `max` of the most recent records, oldest first.
+pub fn log_history(max: usize) -> Vec<LogRecord> {
+    HISTORY.lock(|history| {
+        let history = history.borrow();
+        let Some(history) = history.as_ref() else {
+            return Vec::new();
+        };
+        let (newer, older) = history.records().split_at(history.head);
+        let len = older.len() + newer.len();
+        older
+            .iter()
+            .chain(newer)
+            .skip(len.saturating_sub(max))
+            .copied()
+            .collect()
+    })
+}
@@ -0,0 +1,259 @@
+use super::record::LogRecord;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use core::fmt::{Display, Formatter, Write};
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use embassy_sync::blocking_mutex::CriticalSectionMutex;
+
+const MAX_BUCKETS: usize = 16;
+
+static DEDUP: AtomicBool = AtomicBool::new(true);
+// Records per second and burst size of every module's bucket; a rate of 0, the default, disables
+// the limit.
+static RATE: AtomicU32 = AtomicU32::new(0);
+static BURST: AtomicU32 = AtomicU32::new(50);
+
+/// Records a module lost to the rate limit, and to "last message repeated" collapsing.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Suppressed {
+    pub module: &'static str,
+    pub rate_limited: u32,
+    pub repeated: u32,
+}
+
+impl Display for Suppressed {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "module={} rate_limited={} repeated={}",
+            self.module, self.rate_limited, self.repeated
+        )
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Bucket {
+    counts: Suppressed,
+    // Scaled by 1000 so slow refill rates still accumulate.
+    milli_tokens: u64,
+    refilled_ns: u64,
+    // Dropped since the last record that got through.
+    unreported: u32,
+}
+
+struct Limiter {
+    last: Option<LogRecord>,
+    repeats: u32,
+    buckets: [Option<Bucket>; MAX_BUCKETS],
+}
+
+static LIMITER: CriticalSectionMutex<RefCell<Limiter>> =
+    CriticalSectionMutex::new(RefCell::new(Limiter {
+        last: None,
+        repeats: 0,
+        buckets: [None; MAX_BUCKETS],
+    }));
+
+pub fn set_log_dedup(enabled: bool) {
+    DEDUP.store(enabled, Ordering::SeqCst);
+}
+
+/// Lets each module log `per_second` records on average, in bursts of up to `burst`. Off by default.
+pub fn set_rate_limit(per_second: u32, burst: u32) {
+    RATE.store(per_second, Ordering::SeqCst);
+    BURST.store(burst.max(1), Ordering::SeqCst);
+}
+
+fn summary(of: &LogRecord, args: core::fmt::Arguments<'_>) -> LogRecord {
+    let mut line = LogRecord::new(of.level, of.core, of.module());
+    let _ = line.write_fmt(args);
+    line
+}
+
+impl Limiter {
+    fn bucket(&mut self, module: &'static str, now_ns: u64) -> &mut Bucket {
+        let burst = BURST.load(Ordering::Relaxed) as u64 * 1000;
+        let slot = match self
+            .buckets
+            .iter()
+            .position(|b| b.is_some_and(|b| b.counts.module == module))
+        {
+            Some(slot) => slot,
+            // Full table: hand over the bucket that has been quiet the longest.
+            None => self
+                .buckets
+                .iter()
+                .position(Option::is_none)
+                .unwrap_or_else(|| {
+                    (0..MAX_BUCKETS)
+                        .min_by_key(|&i| self.buckets[i].map_or(0, |b| b.refilled_ns))
+                        .unwrap_or(0)
+                }),
+        };
+        let fresh = Bucket {
+            counts: Suppressed {
+                module,
+                ..Default::default()
+            },
+            milli_tokens: burst,
+            refilled_ns: now_ns,
+            unreported: 0,
+        };
+        let bucket = &mut self.buckets[slot];
+        if !bucket.is_some_and(|b| b.counts.module == module) {
+            *bucket = Some(fresh);
+        }
+        bucket.get_or_insert(fresh)
+    }
+
+    // Collapses a repeat of the previous record. Otherwise returns the summary of the run it ends.
+    fn deduplicate(
+        &mut self,
+        line: &LogRecord,
+        module: &'static str,
+    ) -> Result<Option<LogRecord>, ()> {
+        if self.last.is_some_and(|last| last.same_as(line)) {
+            self.repeats += 1;
+            self.bucket(module, line.timestamp_ns).counts.repeated += 1;
+            return Err(());
+        }
+        let ended = match self.last.replace(*line) {
+            Some(last) if self.repeats > 0 => Some(summary(
+                &last,
+                format_args!("last message repeated {} times", self.repeats),
+            )),
+            _ => None,
+        };
+        self.repeats = 0;
+        Ok(ended)
+    }
+
+    fn take_token(
+        &mut self,
+        line: &LogRecord,
+        module: &'static str,
+    ) -> Result<Option<LogRecord>, ()> {
+        let rate = RATE.load(Ordering::Relaxed) as u64;
+        if rate == 0 {
+            return Ok(None);
+        }
+        let burst = BURST.load(Ordering::Relaxed) as u64 * 1000;
+        let now = line.timestamp_ns;
+        let bucket = self.bucket(module, now);
+        let elapsed_ns = now.saturating_sub(bucket.refilled_ns);
+        bucket.milli_tokens = (bucket.milli_tokens + elapsed_ns * rate / 1_000_000).min(burst);
+        bucket.refilled_ns = now;
+        if bucket.milli_tokens < 1000 {
+            bucket.counts.rate_limited += 1;
+            bucket.unreported += 1;
+            return Err(());
+        }
+        bucket.milli_tokens -= 1000;
+        let dropped = core::mem::take(&mut bucket.unreported);
+        Ok((dropped > 0).then(|| {
+            summary(
+                line,
+                format_args!("{dropped} records suppressed by rate limit"),
+            )
+        }))
+    }
+}
+
+/// Decides whether `line` goes out, returned as the `bool`. The notes are about earlier records
+/// that were held back and go out before `line`, even when `line` itself is suppressed.
+pub(crate) fn admit(line: &LogRecord, module: &'static str) -> ([Option<LogRecord>; 2], bool) {
+    LIMITER.lock(|limiter| {
+        let mut limiter = limiter.borrow_mut();
+        let repeated = if DEDUP.load(Ordering::Relaxed) {
+            match limiter.deduplicate(line, module) {
+                Ok(ended) => ended,
+                Err(()) => return ([None, None], false),
+            }
+        } else {
+            None
+        };
+        match limiter.take_token(line, module) {
+            Ok(limited) => ([repeated, limited], true),
+            Err(()) => ([repeated, None], false),
+        }
+    })
+}
+
+/// Ends a pending run of repeats, returning its "last message repeated" note.
+pub(crate) fn flush_repeats() -> Option<LogRecord> {
+    LIMITER.lock(|limiter| {
+        let mut limiter = limiter.borrow_mut();
+        let repeats = core::mem::take(&mut limiter.repeats);
+        let last = limiter.last.take()?;
+        (repeats > 0).then(|| summary(&last, format_args!("last message repeated {repeats} times")))
+    })
+}
+
+/// Suppression counters of the modules currently tracked (the most recent `MAX_BUCKETS`).
+pub fn suppressed_records() -> Vec<Suppressed> {
+    LIMITER.lock(|limiter| {
+        limiter
+            .borrow()
+            .buckets
+            .iter()
+            .flatten()
+            .map(|bucket| bucket.counts)
+            .filter(|counts| counts.rate_limited > 0 || counts.repeated > 0)
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::Level;
+
+    const MODULE: &str = "xapi::limit_test";
+
+    fn line(message: &str, timestamp_ns: u64) -> LogRecord {
+        let mut line = LogRecord::new(Level::Info, 0, MODULE);
+        let _ = line.write_str(message);
+        line.timestamp_ns = timestamp_ns;
+        line
+    }
+
+    #[test]
+    fn repeats_collapse_into_one_note() {
+        let mut limiter = Limiter {
+            last: None,
+            repeats: 0,
+            buckets: [None; MAX_BUCKETS],
+        };
+        assert!(matches!(
+            limiter.deduplicate(&line("a", 0), MODULE),
+            Ok(None)
+        ));
+        assert!(limiter.deduplicate(&line("a", 1), MODULE).is_err());
+        assert!(limiter.deduplicate(&line("a", 2), MODULE).is_err());
+        let note = limiter.deduplicate(&line("b", 3), MODULE).unwrap().unwrap();
+        assert_eq!(note.message(), "last message repeated 2 times");
+        assert_eq!(note.module(), MODULE);
+        assert_eq!(limiter.bucket(MODULE, 3).counts.repeated, 2);
+        assert!(matches!(
+            limiter.deduplicate(&line("a", 4), MODULE),
+            Ok(None)
+        ));
+    }
+
+    // The only test that goes through the shared limiter, so it owns RATE and BURST.
+    #[test]
+    fn admit_refills_at_the_set_rate() {
+        set_rate_limit(1, 1);
+        let (notes, admitted) = admit(&line("first", 1_000), MODULE);
+        assert!(admitted && notes.iter().all(Option::is_none));
+        let (_, admitted) = admit(&line("second", 1_000), MODULE);
+        assert!(!admitted);
+        let (_, admitted) = admit(&line("third", 500_001_000), MODULE);
+        assert!(!admitted);
+        let (notes, admitted) = admit(&line("fourth", 1_000_001_000), MODULE);
+        assert!(admitted);
+        let note = notes[1].as_ref().unwrap();
+        assert_eq!(note.message(), "2 records suppressed by rate limit");
+        set_rate_limit(0, 50);
+    }
+}
@@ -0,0 +1,82 @@
+#[cfg_attr(not(all(target_arch = "xtensa")), path = "sink_hosted.rs")]
+#[cfg_attr(all(target_arch = "xtensa", target_os = "none"), path = "sink_esp.rs")]
+#[cfg_attr(
+    all(target_arch = "xtensa", target_os = "espidf"),
+    path = "sink_idf.rs"
+)]
+mod sink;
+
+mod deferred;
+// On the ESP32-S3, esp-println's `defmt-espflash` logger carries the frames over the UART.
+#[cfg(all(feature = "defmt", not(target_arch = "xtensa")))]
+mod defmt_hosted;
+mod filter;
+mod format;
+mod history;
+mod limit;
+mod record;
+mod task;
+pub use deferred::{LogMode, dropped_records, flush_logs, log_drain, log_mode, set_log_mode};
+pub use filter::*;
+pub use format::{LogFormat, log_format, set_log_format};
+pub use history::{DEFAULT_HISTORY, enable_log_history, log_history};
+pub use limit::{Suppressed, set_log_dedup, set_rate_limit, suppressed_records};
+pub use record::{LogRecord, MESSAGE_LEN, MODULE_LEN};
+pub use task::{NamedSpawn, TaskTag};
+
+use crate::osdep::mem::current_core;
+use core::fmt::{Arguments, Write};
+use log::{Metadata, Record};
+
+#[cfg(feature = "defmt")]
+defmt::timestamp!("{=u64:us}", crate::osdep::time::epoch_ns() / 1_000);
+
+// Keeps `line` in the history and writes it out. `args` is the untruncated message, when at hand.
+fn emit(line: &LogRecord, args: Option<Arguments<'_>>) {
+    if history::history_enabled() {
+        history::remember(line);
+    }
+    match (log_mode(), args) {
+        (LogMode::Inline, Some(args)) => sink::write_line(&format::Line {
+            record: line,
+            message: &args,
+        }),
+        (LogMode::Inline, None) => format::write_record(line),
+        (LogMode::Deferred, _) => deferred::defer(line),
+    }
+}
+
+/// The `log` backend on every target: filters, collapses repeats, rate-limits per module, keeps
+/// the history, then writes inline or queues for [`log_drain`].
+pub struct XapiLogger;
+pub static LOGGER: XapiLogger = XapiLogger;
+
+impl log::Log for XapiLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        log_enabled(metadata.level(), metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        // Same key as `enabled`, so `log_enabled!` and the records agree.
+        let module = record.target();
+        if !log_enabled(record.level(), module) {
+            return;
+        }
+        let mut line = LogRecord::new(record.level(), current_core(), module);
+        let _ = write!(line, "{}", record.args());
+        let (notes, admitted) = limit::admit(&line, record.module_path_static().unwrap_or("root"));
+        for note in notes.iter().flatten() {
+            emit(note, None);
+        }
+        if admitted {
+            emit(&line, Some(*record.args()));
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(note) = limit::flush_repeats() {
+            emit(&note, None);
+        }
+        flush_logs();
+    }
+}
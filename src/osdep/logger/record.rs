@@ -0,0 +1,94 @@
+use super::format::Line;
+use super::task::TaskTag;
+use core::fmt;
+use log::Level;
+
+pub const MODULE_LEN: usize = 64;
+pub const MESSAGE_LEN: usize = 160;
+
+/// A log line copied out of `log::Record`, so it can be queued and written out later.
+#[derive(Clone, Copy)]
+pub struct LogRecord {
+    pub level: Level,
+    pub core: u8,
+    pub task: TaskTag,
+    pub timestamp_ns: u64,
+    module: [u8; MODULE_LEN],
+    module_len: u8,
+    message: [u8; MESSAGE_LEN],
+    message_len: u8,
+    // FNV-1a over the whole message, including any part cut off.
+    digest: u32,
+}
+
+// Longest prefix of `s` that fits in `room` bytes without splitting a character.
+fn fitting(s: &str, room: usize) -> &[u8] {
+    let mut take = s.len().min(room);
+    while !s.is_char_boundary(take) {
+        take -= 1;
+    }
+    &s.as_bytes()[..take]
+}
+
+impl LogRecord {
+    pub fn new(level: Level, core: u8, module: &str) -> Self {
+        let mut record = LogRecord {
+            level,
+            core,
+            task: TaskTag::current(),
+            timestamp_ns: crate::osdep::time::epoch_ns(),
+            module: [0; MODULE_LEN],
+            module_len: 0,
+            message: [0; MESSAGE_LEN],
+            message_len: 0,
+            digest: 0x811c_9dc5,
+        };
+        let module = fitting(module, MODULE_LEN);
+        record.module[..module.len()].copy_from_slice(module);
+        record.module_len = module.len() as u8;
+        record
+    }
+
+    pub fn module(&self) -> &str {
+        core::str::from_utf8(&self.module[..self.module_len as usize]).unwrap_or("?")
+    }
+
+    pub fn message(&self) -> &str {
+        core::str::from_utf8(&self.message[..self.message_len as usize]).unwrap_or("?")
+    }
+
+    /// Same level, module and full message text.
+    pub fn same_as(&self, other: &LogRecord) -> bool {
+        self.level == other.level
+            && self.digest == other.digest
+            && self.message() == other.message()
+            && self.module() == other.module()
+    }
+}
+
+/// Appends to the message; anything past `MESSAGE_LEN` bytes is cut off.
+impl fmt::Write for LogRecord {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.digest = s.bytes().fold(self.digest, |hash, byte| {
+            (hash ^ byte as u32).wrapping_mul(0x0100_0193)
+        });
+        let at = self.message_len as usize;
+        let bytes = fitting(s, MESSAGE_LEN - at);
+        self.message[at..at + bytes.len()].copy_from_slice(bytes);
+        self.message_len += bytes.len() as u8;
+        Ok(())
+    }
+}
+
+/// Laid out in the current `LogFormat`.
+impl fmt::Display for LogRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(
+            &Line {
+                record: self,
+                message: &self.message(),
+            },
+            f,
+        )
+    }
+}
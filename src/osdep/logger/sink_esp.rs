@@ -0,0 +1,6 @@
+use core::fmt::Display;
+use esp_println::println;
+
+pub fn write_line(line: &dyn Display) {
+    println!("{line}");
+}
@@ -0,0 +1,5 @@
+use core::fmt::Display;
+
+pub fn write_line(line: &dyn Display) {
+    println!("{line}");
+}
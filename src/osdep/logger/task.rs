@@ -0,0 +1,131 @@
+use crate::osdep::mem::current_core;
+use core::cell::{Cell, RefCell};
+use core::fmt::{Display, Formatter};
+use core::sync::atomic::{AtomicU32, Ordering};
+use embassy_executor::{SpawnError, SpawnToken, Spawner};
+use embassy_sync::blocking_mutex::CriticalSectionMutex;
+
+const MAX_CORES: usize = 2;
+const MAX_NAMED: usize = 32;
+
+// Task being polled on each core; 0 outside of any task.
+static CURRENT: [AtomicU32; MAX_CORES] = [const { AtomicU32::new(0) }; MAX_CORES];
+// Name handed to `spawn_named` on each core, picked up by the executor's task-created hook.
+static PENDING: CriticalSectionMutex<Cell<[Option<&'static str>; MAX_CORES]>> =
+    CriticalSectionMutex::new(Cell::new([None; MAX_CORES]));
+static NAMES: CriticalSectionMutex<RefCell<[Option<(u32, &'static str)>; MAX_NAMED]>> =
+    CriticalSectionMutex::new(RefCell::new([None; MAX_NAMED]));
+
+fn core_slot() -> usize {
+    (current_core() as usize).min(MAX_CORES - 1)
+}
+
+/// The embassy task a record was logged from.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TaskTag {
+    /// Executor's id for the task, 0 when logged outside a task.
+    pub id: u32,
+    pub name: Option<&'static str>,
+}
+
+impl TaskTag {
+    pub fn current() -> TaskTag {
+        let id = CURRENT[core_slot()].load(Ordering::Relaxed);
+        let name = match id {
+            0 => None,
+            id => NAMES.lock(|names| {
+                names
+                    .borrow()
+                    .iter()
+                    .flatten()
+                    .find(|(task, _)| *task == id)
+                    .map(|(_, name)| *name)
+            }),
+        };
+        TaskTag { id, name }
+    }
+}
+
+/// The task's name, its id when it has none, `-` outside of tasks.
+impl Display for TaskTag {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match (self.id, self.name) {
+            (_, Some(name)) => f.pad(name),
+            (0, None) => f.pad("-"),
+            (id, None) => write!(f, "#{id:x}"),
+        }
+    }
+}
+
+/// Spawns like `Spawner::spawn`, and labels the task's log lines with `name`. Names only show
+/// up with the `task_names` feature.
+pub trait NamedSpawn {
+    fn spawn_named<S>(&self, name: &'static str, token: SpawnToken<S>) -> Result<(), SpawnError>;
+}
+
+impl NamedSpawn for Spawner {
+    fn spawn_named<S>(&self, name: &'static str, token: SpawnToken<S>) -> Result<(), SpawnError> {
+        let core = core_slot();
+        let set = |name| {
+            PENDING.lock(|pending| {
+                let mut names = pending.get();
+                names[core] = name;
+                pending.set(names);
+            })
+        };
+        set(Some(name));
+        let spawned = self.spawn(token);
+        set(None);
+        spawned
+    }
+}
+
+// Callbacks of embassy-executor's `trace` feature.
+#[cfg(feature = "task_names")]
+mod hooks {
+    use super::*;
+
+    #[unsafe(no_mangle)]
+    fn _embassy_trace_task_new(_executor_id: u32, task_id: u32) {
+        let Some(name) = PENDING.lock(|pending| pending.get()[core_slot()]) else {
+            return;
+        };
+        NAMES.lock(|names| {
+            let mut names = names.borrow_mut();
+            let free = names.iter().position(Option::is_none);
+            if let Some(slot) = free {
+                names[slot] = Some((task_id, name));
+            }
+        });
+    }
+
+    #[unsafe(no_mangle)]
+    fn _embassy_trace_task_end(_executor_id: u32, task_id: u32) {
+        NAMES.lock(|names| {
+            for slot in names.borrow_mut().iter_mut() {
+                if slot.is_some_and(|(task, _)| task == task_id) {
+                    *slot = None;
+                }
+            }
+        });
+    }
+
+    #[unsafe(no_mangle)]
+    fn _embassy_trace_task_exec_begin(_executor_id: u32, task_id: u32) {
+        CURRENT[core_slot()].store(task_id, Ordering::Relaxed);
+    }
+
+    #[unsafe(no_mangle)]
+    fn _embassy_trace_task_exec_end(_executor_id: u32, _task_id: u32) {
+        CURRENT[core_slot()].store(0, Ordering::Relaxed);
+    }
+
+    #[unsafe(no_mangle)]
+    fn _embassy_trace_poll_start(_executor_id: u32) {}
+
+    #[unsafe(no_mangle)]
+    fn _embassy_trace_task_ready_begin(_executor_id: u32, _task_id: u32) {}
+
+    #[unsafe(no_mangle)]
+    fn _embassy_trace_executor_idle(_executor_id: u32) {}
+}
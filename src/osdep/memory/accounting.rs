@@ -0,0 +1,243 @@
+use alloc::vec::Vec;
+use core::alloc::{AllocError, Layout};
+use core::cell::RefCell;
+use core::fmt::{Display, Formatter};
+use core::future::Future;
+use core::pin::Pin;
+use core::ptr::NonNull;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::task::{Context, Poll};
+use embassy_sync::blocking_mutex::CriticalSectionMutex;
+
+const MAX_TAGS: usize = 16;
+const UNTAGGED: usize = 0;
+
+struct TagCounters {
+    current: AtomicUsize,
+    peak: AtomicUsize,
+    allocations: AtomicUsize,
+}
+
+static COUNTERS: [TagCounters; MAX_TAGS] = [const {
+    TagCounters {
+        current: AtomicUsize::new(0),
+        peak: AtomicUsize::new(0),
+        allocations: AtomicUsize::new(0),
+    }
+}; MAX_TAGS];
+static NAMES: CriticalSectionMutex<RefCell<[Option<&'static str>; MAX_TAGS]>> =
+    CriticalSectionMutex::new(RefCell::new({
+        let mut names = [None; MAX_TAGS];
+        names[UNTAGGED] = Some("untagged");
+        names
+    }));
+
+#[cfg(all(target_arch = "xtensa", target_os = "none"))]
+static CURRENT: [AtomicUsize; 2] = [const { AtomicUsize::new(UNTAGGED) }; 2];
+#[cfg(all(target_arch = "xtensa", target_os = "none"))]
+fn current_tag() -> usize {
+    CURRENT[esp_hal::system::Cpu::current() as usize].load(Ordering::Relaxed)
+}
+#[cfg(all(target_arch = "xtensa", target_os = "none"))]
+fn set_current_tag(tag: usize) -> usize {
+    CURRENT[esp_hal::system::Cpu::current() as usize].swap(tag, Ordering::Relaxed)
+}
+#[cfg(not(all(target_arch = "xtensa", target_os = "none")))]
+thread_local! {
+    static CURRENT: core::cell::Cell<usize> = const { core::cell::Cell::new(UNTAGGED) };
+}
+#[cfg(not(all(target_arch = "xtensa", target_os = "none")))]
+fn current_tag() -> usize {
+    CURRENT.with(|c| c.get())
+}
+#[cfg(not(all(target_arch = "xtensa", target_os = "none")))]
+fn set_current_tag(tag: usize) -> usize {
+    CURRENT.with(|c| c.replace(tag))
+}
+
+/// Finds or registers `label`; once the table is full new labels are counted as untagged.
+fn tag_id(label: &'static str) -> usize {
+    NAMES.lock(|names| {
+        let mut names = names.borrow_mut();
+        if let Some(id) = names.iter().position(|n| *n == Some(label)) {
+            return id;
+        }
+        match names.iter().position(|n| n.is_none()) {
+            Some(id) => {
+                names[id] = Some(label);
+                id
+            }
+            None => UNTAGGED,
+        }
+    })
+}
+
+/// Charges allocations on this core to `label` until the guard is dropped.
+/// Do not hold it across an `.await`; use [`tagged`] for async code.
+pub struct TagScope {
+    previous: usize,
+}
+
+impl Drop for TagScope {
+    fn drop(&mut self) {
+        set_current_tag(self.previous);
+    }
+}
+
+pub fn scope(label: &'static str) -> TagScope {
+    TagScope {
+        previous: set_current_tag(tag_id(label)),
+    }
+}
+
+/// Charges everything `future` allocates while it is being polled to `label`, on either heap.
+pub fn tagged<F: Future>(label: &'static str, future: F) -> Tagged<F> {
+    Tagged {
+        tag: tag_id(label),
+        future,
+    }
+}
+
+pub struct Tagged<F> {
+    tag: usize,
+    future: F,
+}
+
+impl<F: Future> Future for Tagged<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = unsafe { self.get_unchecked_mut() };
+        let previous = set_current_tag(this.tag);
+        let result = unsafe { Pin::new_unchecked(&mut this.future) }.poll(cx);
+        set_current_tag(previous);
+        result
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TagUsage {
+    pub tag: &'static str,
+    pub current: usize,
+    pub peak: usize,
+    pub allocations: usize,
+}
+
+impl Display for TagUsage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "tag={} current={} peak={} allocations={}",
+            self.tag, self.current, self.peak, self.allocations
+        )
+    }
+}
+
+fn usage_of(id: usize, tag: &'static str) -> TagUsage {
+    let counters = &COUNTERS[id];
+    TagUsage {
+        tag,
+        current: counters.current.load(Ordering::SeqCst),
+        peak: counters.peak.load(Ordering::SeqCst),
+        allocations: counters.allocations.load(Ordering::SeqCst),
+    }
+}
+
+pub fn tag_usage(label: &str) -> Option<TagUsage> {
+    let names = NAMES.lock(|names| *names.borrow());
+    names
+        .iter()
+        .enumerate()
+        .find_map(|(id, name)| name.filter(|n| *n == label).map(|n| usage_of(id, n)))
+}
+
+/// Usage of every registered tag, heaviest first.
+pub fn task_usage() -> Vec<TagUsage> {
+    let names = NAMES.lock(|names| *names.borrow());
+    let mut usage: Vec<TagUsage> = names
+        .iter()
+        .enumerate()
+        .filter_map(|(id, name)| name.map(|n| usage_of(id, n)))
+        .collect();
+    usage.sort_by(|a, b| b.current.cmp(&a.current));
+    usage
+}
+
+pub fn reset_tag_peaks() {
+    for counters in &COUNTERS {
+        counters
+            .peak
+            .store(counters.current.load(Ordering::SeqCst), Ordering::SeqCst);
+    }
+}
+
+#[cfg(feature = "mem_accounting")]
+mod header {
+    use super::{AllocError, COUNTERS, Layout, NonNull, current_tag};
+    use core::sync::atomic::Ordering;
+
+    // The owning tag is kept in a word just in front of the caller's bytes.
+    fn outer_layout(layout: Layout) -> Option<(Layout, usize)> {
+        let align = layout.align().max(align_of::<usize>());
+        let offset = size_of::<usize>().next_multiple_of(align);
+        let size = offset.checked_add(layout.size())?;
+        Some((Layout::from_size_align(size, align).ok()?, offset))
+    }
+
+    pub(crate) fn allocate(
+        layout: Layout,
+        alloc: impl FnOnce(Layout) -> Result<NonNull<[u8]>, AllocError>,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let (outer, offset) = outer_layout(layout).ok_or(AllocError)?;
+        let base = alloc(outer)?.as_ptr() as *mut u8;
+        let tag = current_tag();
+        let counters = &COUNTERS[tag];
+        let current = counters.current.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+        counters.peak.fetch_max(current, Ordering::Relaxed);
+        counters.allocations.fetch_add(1, Ordering::Relaxed);
+        unsafe {
+            let user = base.add(offset);
+            (user.sub(size_of::<usize>()) as *mut usize).write(tag);
+            Ok(NonNull::slice_from_raw_parts(
+                NonNull::new_unchecked(user),
+                layout.size(),
+            ))
+        }
+    }
+
+    pub(crate) unsafe fn deallocate(
+        ptr: NonNull<u8>,
+        layout: Layout,
+        dealloc: impl FnOnce(NonNull<u8>, Layout),
+    ) {
+        let Some((outer, offset)) = outer_layout(layout) else {
+            return;
+        };
+        unsafe {
+            let user = ptr.as_ptr();
+            let tag = (user.sub(size_of::<usize>()) as *const usize).read();
+            if let Some(counters) = COUNTERS.get(tag) {
+                counters.current.fetch_sub(layout.size(), Ordering::Relaxed);
+            }
+            dealloc(NonNull::new_unchecked(user.sub(offset)), outer);
+        }
+    }
+}
+#[cfg(feature = "mem_accounting")]
+pub(crate) use header::{allocate, deallocate};
+
+#[cfg(not(feature = "mem_accounting"))]
+pub(crate) fn allocate(
+    layout: Layout,
+    alloc: impl FnOnce(Layout) -> Result<NonNull<[u8]>, AllocError>,
+) -> Result<NonNull<[u8]>, AllocError> {
+    alloc(layout)
+}
+#[cfg(not(feature = "mem_accounting"))]
+pub(crate) unsafe fn deallocate(
+    ptr: NonNull<u8>,
+    layout: Layout,
+    dealloc: impl FnOnce(NonNull<u8>, Layout),
+) {
+    dealloc(ptr, layout)
+}
@@ -0,0 +1,236 @@
+use super::trace::TraceHeap;
+use core::alloc::{AllocError, Layout};
+use core::ptr::NonNull;
+
+/// Result of walking every live guarded block.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HeapCheck {
+    pub checked: usize,
+    pub corrupted: usize,
+}
+
+#[cfg(feature = "heap_guard")]
+mod guarded {
+    use super::HeapCheck;
+    use crate::fmt::Dbg;
+    use crate::osdep::memory::trace::TraceHeap;
+    use core::alloc::{AllocError, Layout};
+    use core::cell::RefCell;
+    use core::ptr::NonNull;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use embassy_sync::blocking_mutex::CriticalSectionMutex;
+
+    const GUARD: usize = 16;
+    const GUARD_BYTE: u8 = 0xFD;
+    const POISON_BYTE: u8 = 0xDD;
+    const MAGIC: usize = 0x6ea9_b10c;
+
+    #[repr(C)]
+    struct BlockHeader {
+        prev: *mut BlockHeader,
+        next: *mut BlockHeader,
+        size: usize,
+        heap: TraceHeap,
+        magic: usize,
+    }
+
+    struct BlockList {
+        head: *mut BlockHeader,
+    }
+    unsafe impl Send for BlockList {}
+
+    static BLOCKS: CriticalSectionMutex<RefCell<BlockList>> =
+        CriticalSectionMutex::new(RefCell::new(BlockList {
+            head: core::ptr::null_mut(),
+        }));
+    static CORRUPTED: AtomicUsize = AtomicUsize::new(0);
+
+    #[derive(Clone, Copy, Debug)]
+    enum Damage {
+        Header,
+        Front,
+        Tail,
+    }
+
+    /// Layout actually requested from the heap, and where the caller's bytes start in it.
+    fn outer_layout(layout: Layout) -> Option<(Layout, usize)> {
+        let align = layout.align().max(align_of::<BlockHeader>());
+        let offset = (size_of::<BlockHeader>() + GUARD).next_multiple_of(align);
+        let size = offset.checked_add(layout.size())?.checked_add(GUARD)?;
+        Some((Layout::from_size_align(size, align).ok()?, offset))
+    }
+
+    unsafe fn header_of(user: *mut u8) -> *mut BlockHeader {
+        unsafe { user.sub(GUARD + size_of::<BlockHeader>()) as *mut BlockHeader }
+    }
+
+    unsafe fn check(header: *const BlockHeader) -> Option<Damage> {
+        unsafe {
+            if (*header).magic != MAGIC {
+                return Some(Damage::Header);
+            }
+            let user = (header as *const u8).add(size_of::<BlockHeader>() + GUARD);
+            let front = core::slice::from_raw_parts(user.sub(GUARD), GUARD);
+            if front.iter().any(|b| *b != GUARD_BYTE) {
+                return Some(Damage::Front);
+            }
+            let tail = core::slice::from_raw_parts(user.add((*header).size), GUARD);
+            if tail.iter().any(|b| *b != GUARD_BYTE) {
+                return Some(Damage::Tail);
+            }
+            None
+        }
+    }
+
+    fn report(user: *const u8, size: usize, heap: TraceHeap, damage: Damage) {
+        error!(
+            "heap guard: {} block {:#x} size={} corrupted ({})",
+            Dbg(heap),
+            user as usize,
+            size,
+            Dbg(damage)
+        );
+    }
+
+    pub(crate) fn allocate(
+        layout: Layout,
+        heap: TraceHeap,
+        alloc: impl FnOnce(Layout) -> Result<NonNull<[u8]>, AllocError>,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let (outer, offset) = outer_layout(layout).ok_or(AllocError)?;
+        let base = alloc(outer)?.as_ptr() as *mut u8;
+        unsafe {
+            let user = base.add(offset);
+            let header = header_of(user);
+            user.sub(GUARD).write_bytes(GUARD_BYTE, GUARD);
+            user.add(layout.size()).write_bytes(GUARD_BYTE, GUARD);
+            BLOCKS.lock(|list| {
+                let mut list = list.borrow_mut();
+                header.write(BlockHeader {
+                    prev: core::ptr::null_mut(),
+                    next: list.head,
+                    size: layout.size(),
+                    heap,
+                    magic: MAGIC,
+                });
+                if !list.head.is_null() {
+                    (*list.head).prev = header;
+                }
+                list.head = header;
+            });
+            Ok(NonNull::slice_from_raw_parts(
+                NonNull::new_unchecked(user),
+                layout.size(),
+            ))
+        }
+    }
+
+    /// Checks and unlinks the block, poisons it and hands the underlying allocation to `dealloc`.
+    /// A block whose header is damaged cannot be unlinked safely and is leaked instead.
+    pub(crate) unsafe fn deallocate(
+        ptr: NonNull<u8>,
+        layout: Layout,
+        heap: TraceHeap,
+        dealloc: impl FnOnce(NonNull<u8>, Layout),
+    ) {
+        let Some((outer, offset)) = outer_layout(layout) else {
+            return;
+        };
+        unsafe {
+            let user = ptr.as_ptr();
+            let header = header_of(user);
+            let damage = check(header);
+            if let Some(damage) = damage {
+                CORRUPTED.fetch_add(1, Ordering::SeqCst);
+                report(user, layout.size(), heap, damage);
+                if matches!(damage, Damage::Header) {
+                    return;
+                }
+            }
+            BLOCKS.lock(|list| {
+                let mut list = list.borrow_mut();
+                let BlockHeader { prev, next, .. } = header.read();
+                if prev.is_null() {
+                    list.head = next;
+                } else {
+                    (*prev).next = next;
+                }
+                if !next.is_null() {
+                    (*next).prev = prev;
+                }
+            });
+            let base = user.sub(offset);
+            base.write_bytes(POISON_BYTE, outer.size());
+            dealloc(NonNull::new_unchecked(base), outer);
+        }
+    }
+
+    /// Checks every live guarded block, on both heaps.
+    pub fn verify_heap() -> HeapCheck {
+        const REPORTED: usize = 8;
+        let mut found: [Option<(usize, usize, TraceHeap, Damage)>; REPORTED] = [None; REPORTED];
+        let mut result = HeapCheck::default();
+        BLOCKS.lock(|list| {
+            let list = list.borrow();
+            let mut header = list.head;
+            while !header.is_null() {
+                result.checked += 1;
+                unsafe {
+                    if let Some(damage) = check(header) {
+                        if result.corrupted < REPORTED {
+                            let user = (header as usize) + size_of::<BlockHeader>() + GUARD;
+                            found[result.corrupted] =
+                                Some((user, (*header).size, (*header).heap, damage));
+                        }
+                        result.corrupted += 1;
+                        if matches!(damage, Damage::Header) {
+                            // The links can no longer be trusted.
+                            break;
+                        }
+                    }
+                    header = (*header).next;
+                }
+            }
+        });
+        // Log outside the lock, the logger may allocate.
+        for (user, size, heap, damage) in found.into_iter().flatten() {
+            report(user as *const u8, size, heap, damage);
+        }
+        result
+    }
+
+    pub fn corrupted_frees() -> usize {
+        CORRUPTED.load(Ordering::SeqCst)
+    }
+}
+#[cfg(feature = "heap_guard")]
+pub(crate) use guarded::{allocate, deallocate};
+#[cfg(feature = "heap_guard")]
+pub use guarded::{corrupted_frees, verify_heap};
+
+#[cfg(not(feature = "heap_guard"))]
+pub(crate) fn allocate(
+    layout: Layout,
+    _heap: TraceHeap,
+    alloc: impl FnOnce(Layout) -> Result<NonNull<[u8]>, AllocError>,
+) -> Result<NonNull<[u8]>, AllocError> {
+    alloc(layout)
+}
+#[cfg(not(feature = "heap_guard"))]
+pub(crate) unsafe fn deallocate(
+    ptr: NonNull<u8>,
+    layout: Layout,
+    _heap: TraceHeap,
+    dealloc: impl FnOnce(NonNull<u8>, Layout),
+) {
+    dealloc(ptr, layout)
+}
+/// Without the `heap_guard` feature there are no guarded blocks to check.
+#[cfg(not(feature = "heap_guard"))]
+pub fn verify_heap() -> HeapCheck {
+    HeapCheck::default()
+}
+#[cfg(not(feature = "heap_guard"))]
+pub fn corrupted_frees() -> usize {
+    0
+}
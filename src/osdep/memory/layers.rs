@@ -0,0 +1,31 @@
+use super::trace::TraceHeap;
+use super::{accounting, guard, mem, pressure};
+use core::alloc::{AllocError, Layout};
+use core::ptr::NonNull;
+
+// Every backend runs its raw heap through the same wrappers: accounting outermost, then guards.
+// Watermarks are checked once the raw heap has changed.
+
+pub(crate) fn allocate(
+    layout: Layout,
+    heap: TraceHeap,
+    raw: impl FnOnce(Layout) -> Result<NonNull<[u8]>, AllocError>,
+) -> Result<NonNull<[u8]>, AllocError> {
+    let block = accounting::allocate(layout, |layout| guard::allocate(layout, heap, raw))?;
+    pressure::note_free(heap, || mem::heap_free(heap));
+    Ok(block)
+}
+
+pub(crate) unsafe fn deallocate(
+    ptr: NonNull<u8>,
+    layout: Layout,
+    heap: TraceHeap,
+    raw: impl FnOnce(NonNull<u8>, Layout),
+) {
+    unsafe {
+        accounting::deallocate(ptr, layout, |ptr, layout| {
+            guard::deallocate(ptr, layout, heap, raw)
+        })
+    }
+    pressure::note_free(heap, || mem::heap_free(heap));
+}
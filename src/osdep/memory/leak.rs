@@ -0,0 +1,233 @@
+use super::trace::{TraceHeap, TraceKind};
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use core::fmt::{Display, Formatter};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use embassy_sync::blocking_mutex::CriticalSectionMutex;
+
+#[cfg(all(target_arch = "xtensa", target_os = "none"))]
+const LIVE_CAPACITY: usize = 256;
+#[cfg(not(all(target_arch = "xtensa", target_os = "none")))]
+const LIVE_CAPACITY: usize = 4096;
+#[cfg(all(target_arch = "xtensa", target_os = "none"))]
+const SITE_DEPTH: usize = 4;
+#[cfg(not(all(target_arch = "xtensa", target_os = "none")))]
+const SITE_DEPTH: usize = 8;
+// Frames of the unwinder, `call_site`, `track` and `trace::record`, which are kept out of line so
+// the count holds. Recording happens after `layers::allocate` has returned, so the layer closures
+// add no frames; a site starts at the backend helper that calls `record` (`trace` on the device,
+// `allocate_in` hosted), then the allocator method and its caller. Adding a call between the
+// allocator and `call_site` means raising this.
+const SITE_SKIP: usize = 4;
+
+/// Program counters of the frames that made an allocation, innermost first.
+pub type CallSite = [usize; SITE_DEPTH];
+
+#[derive(Clone, Copy)]
+struct LiveAlloc {
+    ptr: usize,
+    size: usize,
+    heap: TraceHeap,
+    seq: u64,
+    site: CallSite,
+}
+
+struct LiveTable {
+    entries: [Option<LiveAlloc>; LIVE_CAPACITY],
+    untracked: usize,
+    // Kept under the lock: there are no 64-bit atomics on the ESP32-S3.
+    next_seq: u64,
+}
+
+static LIVE: CriticalSectionMutex<RefCell<LiveTable>> =
+    CriticalSectionMutex::new(RefCell::new(LiveTable {
+        entries: [None; LIVE_CAPACITY],
+        untracked: 0,
+        next_seq: 0,
+    }));
+static LIVE_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+#[cfg(all(target_arch = "xtensa", target_os = "none"))]
+#[inline(never)]
+fn call_site() -> CallSite {
+    let mut site = [0; SITE_DEPTH];
+    let backtrace = esp_backtrace::Backtrace::capture();
+    for (slot, frame) in site
+        .iter_mut()
+        .zip(backtrace.frames().iter().skip(SITE_SKIP))
+    {
+        *slot = frame.program_counter();
+    }
+    site
+}
+#[cfg(not(all(target_arch = "xtensa", target_os = "none")))]
+#[inline(never)]
+fn call_site() -> CallSite {
+    let mut site = [0; SITE_DEPTH];
+    let mut depth = 0;
+    // Unwinding does not touch the Rust allocator, so this is safe to call from inside it.
+    unsafe {
+        backtrace::trace_unsynchronized(|frame| {
+            if depth >= SITE_SKIP {
+                site[depth - SITE_SKIP] = frame.ip() as usize;
+            }
+            depth += 1;
+            depth < SITE_SKIP + SITE_DEPTH
+        });
+    }
+    site
+}
+
+#[inline(never)]
+pub(crate) fn track(kind: TraceKind, heap: TraceHeap, ptr: usize, size: usize) {
+    match kind {
+        TraceKind::Alloc => {
+            let site = call_site();
+            LIVE.lock(|table| {
+                let mut table = table.borrow_mut();
+                let entry = LiveAlloc {
+                    ptr,
+                    size,
+                    heap,
+                    seq: table.next_seq,
+                    site,
+                };
+                table.next_seq += 1;
+                match table.entries.iter_mut().find(|slot| slot.is_none()) {
+                    Some(slot) => {
+                        *slot = Some(entry);
+                        LIVE_COUNT.fetch_add(1, Ordering::SeqCst);
+                    }
+                    None => table.untracked += 1,
+                }
+            });
+        }
+        TraceKind::Free => forget(heap, ptr),
+    }
+}
+
+/// Drops a block from the live table; also called for frees made while tracing is off.
+pub(crate) fn forget(heap: TraceHeap, ptr: usize) {
+    if LIVE_COUNT.load(Ordering::SeqCst) == 0 {
+        return;
+    }
+    LIVE.lock(|table| {
+        let mut table = table.borrow_mut();
+        if let Some(slot) = table
+            .entries
+            .iter_mut()
+            .find(|slot| matches!(slot, Some(live) if live.ptr == ptr && live.heap == heap))
+        {
+            *slot = None;
+            LIVE_COUNT.fetch_sub(1, Ordering::SeqCst);
+        }
+    });
+}
+
+/// A point in the allocation history; only allocations made while tracing is on are seen.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct HeapSnapshot {
+    seq: u64,
+}
+
+pub fn snapshot() -> HeapSnapshot {
+    HeapSnapshot {
+        seq: LIVE.lock(|table| table.borrow().next_seq),
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LeakGroup {
+    pub heap: TraceHeap,
+    pub size: usize,
+    pub site: CallSite,
+    pub count: usize,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct LeakReport {
+    pub groups: Vec<LeakGroup>,
+    /// Allocations that could not be tracked because the live table was full.
+    pub untracked: usize,
+}
+
+impl LeakReport {
+    pub fn is_empty(&self) -> bool {
+        self.groups.is_empty()
+    }
+    pub fn leaked_bytes(&self) -> usize {
+        self.groups.iter().map(|g| g.size * g.count).sum()
+    }
+    pub fn leaked_blocks(&self) -> usize {
+        self.groups.iter().map(|g| g.count).sum()
+    }
+}
+
+impl Display for LeakReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{} bytes in {} blocks still live",
+            self.leaked_bytes(),
+            self.leaked_blocks()
+        )?;
+        if self.untracked > 0 {
+            write!(f, " ({} allocations untracked)", self.untracked)?;
+        }
+        for group in &self.groups {
+            write!(
+                f,
+                "\n  {:?} {} x {} bytes at",
+                group.heap, group.count, group.size
+            )?;
+            for pc in group.site.iter().filter(|pc| **pc != 0) {
+                write!(f, " 0x{pc:x}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Allocations made between `from` and `to` that have not been freed yet,
+/// grouped by heap, size and call site, largest total first.
+pub fn diff(from: &HeapSnapshot, to: &HeapSnapshot) -> LeakReport {
+    let in_range = |live: &LiveAlloc| live.seq >= from.seq && live.seq < to.seq;
+    let count = LIVE.lock(|table| {
+        table
+            .borrow()
+            .entries
+            .iter()
+            .flatten()
+            .filter(|live| in_range(live))
+            .count()
+    });
+    // Allocate outside the lock: the allocation may itself be traced.
+    let mut found: Vec<LiveAlloc> = Vec::with_capacity(count);
+    let untracked = LIVE.lock(|table| {
+        let table = table.borrow();
+        for live in table.entries.iter().flatten().filter(|live| in_range(live)) {
+            if found.len() == found.capacity() {
+                break;
+            }
+            found.push(*live);
+        }
+        table.untracked
+    });
+    let mut groups: Vec<LeakGroup> = Vec::new();
+    for live in found {
+        match groups
+            .iter_mut()
+            .find(|g| g.heap == live.heap && g.size == live.size && g.site == live.site)
+        {
+            Some(group) => group.count += 1,
+            None => groups.push(LeakGroup {
+                heap: live.heap,
+                size: live.size,
+                site: live.site,
+                count: 1,
+            }),
+        }
+    }
+    groups.sort_by(|a, b| (b.size * b.count).cmp(&(a.size * a.count)));
+    LeakReport { groups, untracked }
+}
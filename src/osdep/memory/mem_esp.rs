@@ -5,15 +5,24 @@ use esp_println::println;
 
 mod memory_internal {
 
+    use super::super::layers;
+    use super::super::leak::forget;
+    use super::super::policy::{AllocPolicy, allocate_with};
+    use super::super::slab::pool_stats;
+    use super::super::stack::paint_stack;
+    use super::super::stats::{MemStats, MemStatsReport};
+    use super::super::trace::{TraceHeap, TraceKind, record};
+    use crate::fmt::Disp;
     use core::alloc::{AllocError, Allocator, GlobalAlloc, Layout};
     use core::ptr::NonNull;
     use core::sync::atomic;
-    use core::sync::atomic::AtomicBool;
+    use core::sync::atomic::{AtomicBool, AtomicU8, AtomicUsize};
 
     struct GlobalTracingAlloc(AtomicBool);
     #[derive(Debug)]
-    pub struct PSRAMTracingAlloc(AtomicBool);
-    // #[global_allocator]
+    pub struct PSRAMTracingAlloc(AtomicBool, AtomicU8);
+    // The internal heap goes through `layers` like the hosted one; esp_alloc::HEAP does the work.
+    #[global_allocator]
     static ALLOCATOR: GlobalTracingAlloc = GlobalTracingAlloc(AtomicBool::new(false));
     impl GlobalTracingAlloc {
         fn start_tracing(&self) {
@@ -24,6 +33,15 @@ mod memory_internal {
         }
     }
     impl PSRAMTracingAlloc {
+        pub const fn with_policy(policy: AllocPolicy) -> Self {
+            Self(AtomicBool::new(false), AtomicU8::new(policy as u8))
+        }
+        pub fn policy(&self) -> AllocPolicy {
+            AllocPolicy::from_u8(self.1.load(atomic::Ordering::Relaxed))
+        }
+        pub fn set_policy(&self, policy: AllocPolicy) {
+            self.1.store(policy as u8, atomic::Ordering::Relaxed);
+        }
         fn start_tracing(&self) {
             self.0.store(true, atomic::Ordering::SeqCst);
         }
@@ -31,30 +49,107 @@ mod memory_internal {
             self.0.store(false, atomic::Ordering::SeqCst);
         }
     }
+    fn trace(
+        tracing: &AtomicBool,
+        kind: TraceKind,
+        heap: TraceHeap,
+        ptr: *const u8,
+        layout: Layout,
+    ) {
+        if ptr.is_null() {
+            return;
+        }
+        if tracing.load(atomic::Ordering::Relaxed) {
+            record(kind, heap, ptr, layout.size(), layout.align());
+        } else if kind == TraceKind::Free {
+            forget(heap, ptr as usize);
+        }
+    }
+    const PSRAM_HEAP: TraceHeap = if cfg!(feature = "unified_memory") {
+        TraceHeap::Internal
+    } else {
+        TraceHeap::Psram
+    };
+    static PSRAM_START: AtomicUsize = AtomicUsize::new(0);
+    static PSRAM_END: AtomicUsize = AtomicUsize::new(0);
+    /// Which heap served `ptr`; fallback blocks of a PSRAM handle live in internal RAM.
+    fn heap_of(ptr: NonNull<u8>) -> TraceHeap {
+        let addr = ptr.as_ptr() as usize;
+        let start = PSRAM_START.load(atomic::Ordering::Relaxed);
+        if PSRAM_HEAP == TraceHeap::Psram
+            && (start..PSRAM_END.load(atomic::Ordering::Relaxed)).contains(&addr)
+        {
+            TraceHeap::Psram
+        } else {
+            TraceHeap::Internal
+        }
+    }
+    fn inner_heap(heap: TraceHeap) -> &'static EspHeapInner {
+        match heap {
+            TraceHeap::Internal => &esp_alloc::HEAP,
+            TraceHeap::Psram => &PSRAM_ALLOCATOR_INNER,
+        }
+    }
     unsafe impl GlobalAlloc for GlobalTracingAlloc {
         unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-            unsafe { GlobalAlloc::alloc(&esp_alloc::HEAP, layout) }
+            let ptr = layers::allocate(layout, TraceHeap::Internal, |layout| {
+                let ptr = unsafe { GlobalAlloc::alloc(&esp_alloc::HEAP, layout) };
+                NonNull::new(ptr)
+                    .map(|ptr| NonNull::slice_from_raw_parts(ptr, layout.size()))
+                    .ok_or(AllocError)
+            })
+            .map_or(core::ptr::null_mut(), |block| block.as_ptr() as *mut u8);
+            trace(&self.0, TraceKind::Alloc, TraceHeap::Internal, ptr, layout);
+            ptr
         }
 
         unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            trace(&self.0, TraceKind::Free, TraceHeap::Internal, ptr, layout);
             unsafe {
-                GlobalAlloc::dealloc(&esp_alloc::HEAP, ptr, layout);
+                layers::deallocate(
+                    NonNull::new_unchecked(ptr),
+                    layout,
+                    TraceHeap::Internal,
+                    |ptr, layout| GlobalAlloc::dealloc(&esp_alloc::HEAP, ptr.as_ptr(), layout),
+                );
             }
         }
     }
     unsafe impl Allocator for GlobalTracingAlloc {
         fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
-            Allocator::allocate(&esp_alloc::HEAP, layout)
+            let block = layers::allocate(layout, TraceHeap::Internal, |layout| {
+                Allocator::allocate(&esp_alloc::HEAP, layout)
+            })?;
+            trace(
+                &self.0,
+                TraceKind::Alloc,
+                TraceHeap::Internal,
+                block.as_ptr() as *const u8,
+                layout,
+            );
+            Ok(block)
         }
         unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+            trace(
+                &self.0,
+                TraceKind::Free,
+                TraceHeap::Internal,
+                ptr.as_ptr(),
+                layout,
+            );
             unsafe {
-                Allocator::deallocate(&esp_alloc::HEAP, ptr, layout);
+                layers::deallocate(ptr, layout, TraceHeap::Internal, |ptr, layout| {
+                    Allocator::deallocate(&esp_alloc::HEAP, ptr, layout)
+                });
             }
         }
     }
     impl Clone for PSRAMTracingAlloc {
         fn clone(&self) -> Self {
-            Self(AtomicBool::new(self.0.load(atomic::Ordering::SeqCst)))
+            Self(
+                AtomicBool::new(self.0.load(atomic::Ordering::SeqCst)),
+                AtomicU8::new(self.1.load(atomic::Ordering::SeqCst)),
+            )
         }
     }
     impl Default for PSRAMTracingAlloc {
@@ -69,35 +164,46 @@ mod memory_internal {
     }
     unsafe impl Allocator for PSRAMTracingAlloc {
         fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
-            cfg_if::cfg_if! {
-                if #[cfg(feature = "unified_memory")] {
-                    esp_alloc::HEAP.allocate(layout)
+            allocate_with(self.policy(), layout.size(), |heap| {
+                let heap = if heap == TraceHeap::Psram {
+                    PSRAM_HEAP
                 } else {
-                    Allocator::allocate(&PSRAM_ALLOCATOR_INNER, layout)
-                }
-            }
+                    heap
+                };
+                let block = layers::allocate(layout, heap, |layout| {
+                    Allocator::allocate(inner_heap(heap), layout)
+                })?;
+                trace(
+                    &self.0,
+                    TraceKind::Alloc,
+                    heap,
+                    block.as_ptr() as *const u8,
+                    layout,
+                );
+                Ok(block)
+            })
         }
 
         unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+            let heap = heap_of(ptr);
+            trace(&self.0, TraceKind::Free, heap, ptr.as_ptr(), layout);
             unsafe {
-                cfg_if::cfg_if! {
-                    if #[cfg(feature = "unified_memory")] {
-                        esp_alloc::HEAP.deallocate(ptr, layout)
-                    } else {
-                        Allocator::deallocate(&PSRAM_ALLOCATOR_INNER, ptr, layout)
-                    }
-                }
+                layers::deallocate(ptr, layout, heap, |ptr, layout| {
+                    Allocator::deallocate(inner_heap(heap), ptr, layout)
+                });
             }
         }
     }
     pub type EspHeapInner = esp_alloc::EspHeap;
     pub type EspHeap = PSRAMTracingAlloc;
     pub static PSRAM_ALLOCATOR_INNER: esp_alloc::EspHeap = esp_alloc::EspHeap::empty();
-    pub static PSRAM_ALLOCATOR: EspHeap = PSRAMTracingAlloc(AtomicBool::new(false));
+    pub static PSRAM_ALLOCATOR: EspHeap = PSRAMTracingAlloc::with_policy(AllocPolicy::FailFast);
     pub struct PsramPeriph(pub esp_hal::peripherals::PSRAM<'static>);
 
     pub fn init_psram_heap(psram: PsramPeriph) -> PsramPeriph {
         let (start, size) = esp_hal::psram::psram_raw_parts(&psram.0);
+        PSRAM_START.store(start as usize, atomic::Ordering::Relaxed);
+        PSRAM_END.store(start as usize + size, atomic::Ordering::Relaxed);
         unsafe {
             cfg_if::cfg_if! {
                 if #[cfg(feature = "unified_memory")] {
@@ -117,11 +223,55 @@ mod memory_internal {
         }
         psram
     }
+    fn heap_stats(name: &'static str, heap: &EspHeapInner) -> MemStats {
+        let stats: esp_alloc::HeapStats = heap.stats();
+        let free = stats.size - stats.current_usage;
+        MemStats {
+            name,
+            size: stats.size,
+            used: stats.current_usage,
+            free,
+            // Probing with real allocations could starve the other core, so leave it unknown.
+            largest_free: None,
+            high_water: stats.max_usage,
+        }
+    }
+    pub(crate) fn heap_free(heap: TraceHeap) -> usize {
+        match heap {
+            TraceHeap::Internal => esp_alloc::HEAP.free(),
+            TraceHeap::Psram => PSRAM_ALLOCATOR_INNER.free(),
+        }
+    }
+    pub fn mem_stats() -> [MemStats; 2] {
+        [
+            heap_stats("internal", &esp_alloc::HEAP),
+            heap_stats("psram", &PSRAM_ALLOCATOR_INNER),
+        ]
+    }
     pub fn dump_mem_stats(comment: &str) {
-        let stats: esp_alloc::HeapStats = esp_alloc::HEAP.stats();
-        log::info!("{comment}: \n{}", stats);
-        let stats = PSRAM_ALLOCATOR_INNER.stats();
-        log::info!("{comment} PSRAM: \n{}", stats);
+        let mut stats = mem_stats().to_vec();
+        stats.extend(pool_stats());
+        info!("{}: \n{}", comment, Disp(MemStatsReport(&stats)));
+    }
+
+    unsafe extern "C" {
+        static mut _stack_start_cpu0: u8;
+        static mut _stack_end_cpu0: u8;
+    }
+    /// Paints the main stack below the caller; call it as early as possible.
+    #[inline(never)]
+    pub fn paint_main_stack() {
+        let marker = 0u8;
+        // Keep clear of this frame and the one paint_stack is about to push.
+        let painted_top = core::ptr::addr_of!(marker) as usize - 1024;
+        unsafe {
+            paint_stack(
+                "main",
+                core::ptr::addr_of_mut!(_stack_end_cpu0),
+                painted_top as *mut u8,
+                core::ptr::addr_of_mut!(_stack_start_cpu0),
+            );
+        }
     }
 
     pub fn start_tracing() {
@@ -137,9 +287,25 @@ mod memory_internal {
         PSRAM_ALLOCATOR.stop_tracing();
     }
 }
+pub use super::accounting::{
+    TagScope, TagUsage, Tagged, reset_tag_peaks, scope, tag_usage, tagged, task_usage,
+};
+pub use super::guard::{HeapCheck, corrupted_frees, verify_heap};
+pub use super::leak::{CallSite, HeapSnapshot, LeakGroup, LeakReport, diff, snapshot};
+pub use super::policy::{AllocPolicy, PolicyStats, policy_stats};
+pub use super::pressure::{
+    PressureCallback, PressureEvent, WatermarkId, check_pressure, last_free, pressure_task,
+    register_low_memory, unregister_low_memory,
+};
+pub use super::slab::{SlabPool, pool_stats};
+pub use super::stack::{StackUsage, forget_stack, paint_stack, stack_usage};
+pub use super::stats::{MemStats, MemStatsReport};
+pub use super::trace::{
+    TraceEvent, TraceHeap, TraceKind, current_core, drain_trace, dump_trace, pop_trace,
+};
 pub use memory_internal::*;
 pub fn dump_backtrace(comment: &str) {
-    log::info!("Backtrace: {}\n", comment);
+    info!("Backtrace: {}\n", comment);
     let backtrace = Backtrace::capture();
     for frame in backtrace.frames() {
         println!("0x{:x}", frame.program_counter());
@@ -0,0 +1,427 @@
+use std::backtrace::Backtrace;
+
+mod memory_internal {
+
+    use super::super::layers;
+    use super::super::leak::forget;
+    use super::super::policy::{AllocPolicy, allocate_with};
+    use super::super::slab::pool_stats;
+    use super::super::stack::{forget_stack, paint_stack};
+    use super::super::stats::{MemStats, MemStatsReport};
+    use super::super::trace::{TraceHeap, TraceKind, record};
+    use crate::fmt::Disp;
+    use core::alloc::{AllocError, Allocator, GlobalAlloc, Layout};
+    use core::cell::RefCell;
+    use core::fmt::{Display, Formatter};
+    use core::ptr::NonNull;
+    use core::sync::atomic;
+    use core::sync::atomic::{AtomicBool, AtomicU8, AtomicUsize};
+    use embassy_sync::blocking_mutex::CriticalSectionMutex;
+    use std::alloc::System;
+
+    /// Same figures `esp_alloc::HeapStats` reports on the device.
+    #[derive(Clone, Copy, Debug, Default)]
+    pub struct HeapStats {
+        pub size: usize,
+        pub current_usage: usize,
+        pub max_usage: usize,
+        pub total_allocated: usize,
+        pub total_freed: usize,
+    }
+
+    impl Display for HeapStats {
+        fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+            writeln!(f, "HEAP INFO")?;
+            if self.size == usize::MAX {
+                writeln!(f, "Size: unlimited")?;
+            } else {
+                writeln!(f, "Size: {}", self.size)?;
+            }
+            writeln!(f, "Current usage: {}", self.current_usage)?;
+            writeln!(f, "Max usage: {}", self.max_usage)?;
+            writeln!(f, "Total freed: {}", self.total_freed)?;
+            write!(f, "Total allocated: {}", self.total_allocated)
+        }
+    }
+
+    struct HeapCounters {
+        heap: TraceHeap,
+        capacity: AtomicUsize,
+        used: AtomicUsize,
+        max: AtomicUsize,
+        allocated: AtomicUsize,
+        freed: AtomicUsize,
+    }
+
+    impl HeapCounters {
+        const fn new(heap: TraceHeap, capacity: usize) -> Self {
+            Self {
+                heap,
+                capacity: AtomicUsize::new(capacity),
+                used: AtomicUsize::new(0),
+                max: AtomicUsize::new(0),
+                allocated: AtomicUsize::new(0),
+                freed: AtomicUsize::new(0),
+            }
+        }
+        fn reserve(&self, size: usize) -> bool {
+            let capacity = self.capacity.load(atomic::Ordering::SeqCst);
+            let reserved = self.used.fetch_update(
+                atomic::Ordering::SeqCst,
+                atomic::Ordering::SeqCst,
+                |used| used.checked_add(size).filter(|next| *next <= capacity),
+            );
+            match reserved {
+                Ok(used) => {
+                    self.max.fetch_max(used + size, atomic::Ordering::SeqCst);
+                    self.allocated.fetch_add(size, atomic::Ordering::SeqCst);
+                    true
+                }
+                Err(_) => false,
+            }
+        }
+        fn release(&self, size: usize) {
+            self.used.fetch_sub(size, atomic::Ordering::SeqCst);
+            self.freed.fetch_add(size, atomic::Ordering::SeqCst);
+        }
+        fn stats(&self) -> HeapStats {
+            HeapStats {
+                size: self.capacity.load(atomic::Ordering::SeqCst),
+                current_usage: self.used.load(atomic::Ordering::SeqCst),
+                max_usage: self.max.load(atomic::Ordering::SeqCst),
+                total_allocated: self.allocated.load(atomic::Ordering::SeqCst),
+                total_freed: self.freed.load(atomic::Ordering::SeqCst),
+            }
+        }
+    }
+
+    static INTERNAL: HeapCounters = HeapCounters::new(TraceHeap::Internal, usize::MAX);
+    static PSRAM: HeapCounters = HeapCounters::new(TraceHeap::Psram, 2 * 1024 * 1024);
+    // Both simulated heaps share one address space, so PSRAM blocks are remembered by address
+    // (sorted) to free fallback blocks against the right heap.
+    static PSRAM_BLOCKS: CriticalSectionMutex<RefCell<Vec<usize, System>>> =
+        CriticalSectionMutex::new(RefCell::new(Vec::new_in(System)));
+
+    fn counters(heap: TraceHeap) -> &'static HeapCounters {
+        match heap {
+            TraceHeap::Internal => &INTERNAL,
+            TraceHeap::Psram => &PSRAM,
+        }
+    }
+    fn heap_of(ptr: NonNull<u8>) -> TraceHeap {
+        let addr = ptr.as_ptr() as usize;
+        PSRAM_BLOCKS.lock(|blocks| match blocks.borrow().binary_search(&addr) {
+            Ok(_) => TraceHeap::Psram,
+            Err(_) => TraceHeap::Internal,
+        })
+    }
+    fn remember_psram(ptr: NonNull<u8>, live: bool) {
+        let addr = ptr.as_ptr() as usize;
+        PSRAM_BLOCKS.lock(|blocks| {
+            let mut blocks = blocks.borrow_mut();
+            match (blocks.binary_search(&addr), live) {
+                (Err(at), true) => blocks.insert(at, addr),
+                (Ok(at), false) => {
+                    blocks.remove(at);
+                }
+                _ => {}
+            }
+        });
+    }
+
+    fn allocate_in(
+        heap: &HeapCounters,
+        tracing: &AtomicBool,
+        layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let block = layers::allocate(layout, heap.heap, |layout| {
+            if !heap.reserve(layout.size()) {
+                return Err(AllocError);
+            }
+            System
+                .allocate(layout)
+                .inspect_err(|_| heap.release(layout.size()))
+        })?;
+        if tracing.load(atomic::Ordering::Relaxed) {
+            record(
+                TraceKind::Alloc,
+                heap.heap,
+                block.as_ptr() as *const u8,
+                layout.size(),
+                layout.align(),
+            );
+        }
+        Ok(block)
+    }
+
+    unsafe fn deallocate_in(
+        heap: &HeapCounters,
+        tracing: &AtomicBool,
+        ptr: NonNull<u8>,
+        layout: Layout,
+    ) {
+        if tracing.load(atomic::Ordering::Relaxed) {
+            record(
+                TraceKind::Free,
+                heap.heap,
+                ptr.as_ptr(),
+                layout.size(),
+                layout.align(),
+            );
+        } else {
+            forget(heap.heap, ptr.as_ptr() as usize);
+        }
+        unsafe {
+            layers::deallocate(ptr, layout, heap.heap, |ptr, layout| {
+                System.deallocate(ptr, layout);
+                heap.release(layout.size());
+            })
+        };
+    }
+
+    struct GlobalTracingAlloc(AtomicBool);
+    #[derive(Debug)]
+    pub struct PSRAMTracingAlloc(AtomicBool, AtomicU8);
+    #[global_allocator]
+    static ALLOCATOR: GlobalTracingAlloc = GlobalTracingAlloc(AtomicBool::new(false));
+    impl GlobalTracingAlloc {
+        fn start_tracing(&self) {
+            self.0.store(true, atomic::Ordering::SeqCst);
+        }
+        fn stop_tracing(&self) {
+            self.0.store(false, atomic::Ordering::SeqCst);
+        }
+    }
+    impl PSRAMTracingAlloc {
+        pub const fn with_policy(policy: AllocPolicy) -> Self {
+            Self(AtomicBool::new(false), AtomicU8::new(policy as u8))
+        }
+        pub fn policy(&self) -> AllocPolicy {
+            AllocPolicy::from_u8(self.1.load(atomic::Ordering::Relaxed))
+        }
+        pub fn set_policy(&self, policy: AllocPolicy) {
+            self.1.store(policy as u8, atomic::Ordering::Relaxed);
+        }
+        fn start_tracing(&self) {
+            self.0.store(true, atomic::Ordering::SeqCst);
+        }
+        fn stop_tracing(&self) {
+            self.0.store(false, atomic::Ordering::SeqCst);
+        }
+    }
+    unsafe impl GlobalAlloc for GlobalTracingAlloc {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            allocate_in(&INTERNAL, &self.0, layout)
+                .map(|block| block.as_ptr() as *mut u8)
+                .unwrap_or(core::ptr::null_mut())
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            unsafe {
+                deallocate_in(&INTERNAL, &self.0, NonNull::new_unchecked(ptr), layout);
+            }
+        }
+    }
+    unsafe impl Allocator for GlobalTracingAlloc {
+        fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+            allocate_in(&INTERNAL, &self.0, layout)
+        }
+        unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+            unsafe {
+                deallocate_in(&INTERNAL, &self.0, ptr, layout);
+            }
+        }
+    }
+    impl Clone for PSRAMTracingAlloc {
+        fn clone(&self) -> Self {
+            Self(
+                AtomicBool::new(self.0.load(atomic::Ordering::SeqCst)),
+                AtomicU8::new(self.1.load(atomic::Ordering::SeqCst)),
+            )
+        }
+    }
+    impl Default for PSRAMTracingAlloc {
+        fn default() -> Self {
+            PSRAM_ALLOCATOR.clone()
+        }
+    }
+    impl Default for &'static EspHeap {
+        fn default() -> Self {
+            &PSRAM_ALLOCATOR
+        }
+    }
+    unsafe impl Allocator for PSRAMTracingAlloc {
+        fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+            allocate_with(self.policy(), layout.size(), |heap| match heap {
+                TraceHeap::Psram if !cfg!(feature = "unified_memory") => {
+                    let block = allocate_in(&PSRAM, &self.0, layout)?;
+                    remember_psram(block.cast(), true);
+                    Ok(block)
+                }
+                _ => allocate_in(&INTERNAL, &self.0, layout),
+            })
+        }
+
+        unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+            let heap = heap_of(ptr);
+            if heap == TraceHeap::Psram {
+                remember_psram(ptr, false);
+            }
+            unsafe {
+                deallocate_in(counters(heap), &self.0, ptr, layout);
+            }
+        }
+    }
+    pub type EspHeap = PSRAMTracingAlloc;
+    pub static PSRAM_ALLOCATOR: EspHeap = PSRAMTracingAlloc::with_policy(AllocPolicy::FailFast);
+
+    /// Caps the simulated internal heap; allocations beyond it fail like they would on the device.
+    pub fn set_internal_capacity(bytes: usize) {
+        INTERNAL.capacity.store(bytes, atomic::Ordering::SeqCst);
+    }
+    /// Caps the simulated PSRAM heap, 2 MiB by default to match the device.
+    pub fn set_psram_capacity(bytes: usize) {
+        PSRAM.capacity.store(bytes, atomic::Ordering::SeqCst);
+    }
+    pub fn internal_stats() -> HeapStats {
+        INTERNAL.stats()
+    }
+    pub fn psram_stats() -> HeapStats {
+        PSRAM.stats()
+    }
+    fn mem_stats_of(name: &'static str, heap: &HeapCounters) -> MemStats {
+        let stats = heap.stats();
+        let free = stats.size - stats.current_usage;
+        // The simulated heaps do not fragment.
+        MemStats {
+            name,
+            size: stats.size,
+            used: stats.current_usage,
+            free,
+            largest_free: Some(free),
+            high_water: stats.max_usage,
+        }
+    }
+    pub(crate) fn heap_free(heap: TraceHeap) -> usize {
+        let stats = counters(heap).stats();
+        stats.size.saturating_sub(stats.current_usage)
+    }
+    pub fn mem_stats() -> [MemStats; 2] {
+        [
+            mem_stats_of("internal", &INTERNAL),
+            mem_stats_of("psram", &PSRAM),
+        ]
+    }
+    pub fn dump_mem_stats(comment: &str) {
+        let mut stats = mem_stats().to_vec();
+        stats.extend(pool_stats());
+        info!("{}: \n{}", comment, Disp(MemStatsReport(&stats)));
+    }
+
+    struct PaintedThread(&'static str);
+    impl Drop for PaintedThread {
+        fn drop(&mut self) {
+            forget_stack(self.0);
+        }
+    }
+    thread_local! {
+        static PAINTED: RefCell<Option<PaintedThread>> = const { RefCell::new(None) };
+    }
+
+    /// Paints `size` bytes of the calling thread's stack below the caller, emulating the
+    /// painted core stacks of the device. The thread must have that much stack left; the
+    /// stack is no longer reported once the thread exits.
+    #[inline(never)]
+    pub fn paint_thread_stack(name: &'static str, size: usize) {
+        let marker = 0u8;
+        // Keep clear of this frame and the one paint_stack is about to push.
+        let top = (core::ptr::addr_of!(marker) as usize - 4096) & !15;
+        unsafe {
+            paint_stack(
+                name,
+                (top - size) as *mut u8,
+                top as *mut u8,
+                top as *mut u8,
+            );
+        }
+        PAINTED.with(|painted| *painted.borrow_mut() = Some(PaintedThread(name)));
+    }
+    pub fn paint_main_stack() {
+        paint_thread_stack("main", 256 * 1024);
+    }
+
+    pub fn start_tracing() {
+        ALLOCATOR.start_tracing();
+    }
+    pub fn stop_tracing() {
+        ALLOCATOR.stop_tracing();
+    }
+    pub fn start_other_tracing() {
+        PSRAM_ALLOCATOR.start_tracing();
+    }
+    pub fn stop_other_tracing() {
+        PSRAM_ALLOCATOR.stop_tracing();
+    }
+}
+pub use super::accounting::{
+    TagScope, TagUsage, Tagged, reset_tag_peaks, scope, tag_usage, tagged, task_usage,
+};
+pub use super::guard::{HeapCheck, corrupted_frees, verify_heap};
+pub use super::leak::{CallSite, HeapSnapshot, LeakGroup, LeakReport, diff, snapshot};
+pub use super::policy::{AllocPolicy, PolicyStats, policy_stats};
+pub use super::pressure::{
+    PressureCallback, PressureEvent, WatermarkId, check_pressure, last_free, pressure_task,
+    register_low_memory, unregister_low_memory,
+};
+pub use super::slab::{SlabPool, pool_stats};
+pub use super::stack::{StackUsage, forget_stack, paint_stack, stack_usage};
+pub use super::stats::{MemStats, MemStatsReport};
+pub use super::trace::{
+    TraceEvent, TraceHeap, TraceKind, current_core, drain_trace, dump_trace, pop_trace, set_core_id,
+};
+pub use memory_internal::*;
+pub fn dump_backtrace(comment: &str) {
+    info!("Backtrace: {}\n", comment);
+    let backtrace = Backtrace::force_capture();
+    println!("{backtrace}");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[inline(never)]
+    fn use_stack(bytes: usize) -> u8 {
+        let frame = core::hint::black_box([1u8; 1024]);
+        if bytes > frame.len() {
+            use_stack(bytes - frame.len()).wrapping_add(frame[0])
+        } else {
+            frame[0]
+        }
+    }
+
+    fn usage_of(name: &str) -> Option<StackUsage> {
+        stack_usage().into_iter().find(|usage| usage.name == name)
+    }
+
+    #[test]
+    fn stack_usage_reports_peak_of_painted_thread() {
+        let size = 64 * 1024;
+        let usage = std::thread::Builder::new()
+            .stack_size(256 * 1024)
+            .spawn(move || {
+                paint_thread_stack("test_stack", size);
+                let fresh = usage_of("test_stack").unwrap();
+                use_stack(16 * 1024);
+                (fresh, usage_of("test_stack").unwrap())
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+        let (fresh, used) = usage;
+        assert_eq!(used.size, size);
+        assert!(fresh.peak < 4 * 1024, "{fresh}");
+        assert!(used.peak >= 16 * 1024 && used.headroom() > 0, "{used}");
+        assert!(usage_of("test_stack").is_none());
+    }
+}
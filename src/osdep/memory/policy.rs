@@ -0,0 +1,129 @@
+use super::trace::TraceHeap;
+use core::fmt::{Display, Formatter};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Where a PSRAM allocator handle places its blocks.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(u8)]
+pub enum AllocPolicy {
+    /// PSRAM, falling back to internal RAM once PSRAM is exhausted.
+    PsramFirst,
+    /// Internal RAM only.
+    InternalOnly,
+    /// PSRAM only; fails as soon as PSRAM is exhausted.
+    #[default]
+    FailFast,
+}
+
+impl AllocPolicy {
+    pub const ALL: [AllocPolicy; 3] = [
+        AllocPolicy::PsramFirst,
+        AllocPolicy::InternalOnly,
+        AllocPolicy::FailFast,
+    ];
+
+    pub(crate) const fn from_u8(value: u8) -> Self {
+        match value {
+            0 => AllocPolicy::PsramFirst,
+            1 => AllocPolicy::InternalOnly,
+            _ => AllocPolicy::FailFast,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            AllocPolicy::PsramFirst => "psram_first",
+            AllocPolicy::InternalOnly => "internal_only",
+            AllocPolicy::FailFast => "fail_fast",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PolicyStats {
+    pub policy: AllocPolicy,
+    /// Successful allocations, fallbacks included.
+    pub allocations: usize,
+    pub bytes: usize,
+    /// Allocations that PSRAM could not serve and went to internal RAM instead.
+    pub fallbacks: usize,
+    pub failures: usize,
+}
+
+impl Display for PolicyStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "policy={} allocations={} bytes={} fallbacks={} failures={}",
+            self.policy.name(),
+            self.allocations,
+            self.bytes,
+            self.fallbacks,
+            self.failures
+        )
+    }
+}
+
+struct Counters {
+    allocations: AtomicUsize,
+    bytes: AtomicUsize,
+    fallbacks: AtomicUsize,
+    failures: AtomicUsize,
+}
+
+static COUNTERS: [Counters; 3] = [const {
+    Counters {
+        allocations: AtomicUsize::new(0),
+        bytes: AtomicUsize::new(0),
+        fallbacks: AtomicUsize::new(0),
+        failures: AtomicUsize::new(0),
+    }
+}; 3];
+
+fn count_allocation(policy: AllocPolicy, size: usize) {
+    let counters = &COUNTERS[policy as usize];
+    counters.allocations.fetch_add(1, Ordering::Relaxed);
+    counters.bytes.fetch_add(size, Ordering::Relaxed);
+}
+
+fn count_fallback(policy: AllocPolicy) {
+    COUNTERS[policy as usize]
+        .fallbacks
+        .fetch_add(1, Ordering::Relaxed);
+}
+
+fn count_failure(policy: AllocPolicy) {
+    COUNTERS[policy as usize]
+        .failures
+        .fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn policy_stats(policy: AllocPolicy) -> PolicyStats {
+    let counters = &COUNTERS[policy as usize];
+    PolicyStats {
+        policy,
+        allocations: counters.allocations.load(Ordering::Relaxed),
+        bytes: counters.bytes.load(Ordering::Relaxed),
+        fallbacks: counters.fallbacks.load(Ordering::Relaxed),
+        failures: counters.failures.load(Ordering::Relaxed),
+    }
+}
+
+/// Runs `allocate` against the heaps `policy` allows, in order, and updates its counters.
+pub(crate) fn allocate_with<T, E>(
+    policy: AllocPolicy,
+    size: usize,
+    mut allocate: impl FnMut(TraceHeap) -> Result<T, E>,
+) -> Result<T, E> {
+    let result = match policy {
+        AllocPolicy::InternalOnly => allocate(TraceHeap::Internal),
+        AllocPolicy::FailFast => allocate(TraceHeap::Psram),
+        AllocPolicy::PsramFirst => allocate(TraceHeap::Psram)
+            .or_else(|_| allocate(TraceHeap::Internal).inspect(|_| count_fallback(policy))),
+    };
+    match result {
+        Ok(_) => count_allocation(policy, size),
+        Err(_) => count_failure(policy),
+    }
+    result
+}
@@ -0,0 +1,179 @@
+use super::mem;
+use super::trace::TraceHeap;
+use crate::osdep::time::delay_ns_async;
+use core::cell::RefCell;
+use core::future::poll_fn;
+use core::pin::pin;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::task::Poll;
+use core::time::Duration;
+use embassy_executor::task;
+use embassy_sync::blocking_mutex::CriticalSectionMutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+
+const MAX_WATERMARKS: usize = 8;
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Clone, Copy, Debug)]
+pub struct PressureEvent {
+    pub heap: TraceHeap,
+    pub watermark: usize,
+    pub free: usize,
+}
+
+pub type PressureCallback = fn(&PressureEvent);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WatermarkId(usize);
+
+#[derive(Clone, Copy)]
+struct Watermark {
+    heap: TraceHeap,
+    level: usize,
+    callback: PressureCallback,
+    below: bool,
+    pending: Option<usize>,
+}
+
+static WATERMARKS: CriticalSectionMutex<RefCell<[Option<Watermark>; MAX_WATERMARKS]>> =
+    CriticalSectionMutex::new(RefCell::new([None; MAX_WATERMARKS]));
+// Per heap: the highest registered watermark, and whether any is currently crossed.
+static HIGHEST: [AtomicUsize; 2] = [const { AtomicUsize::new(0) }; 2];
+static ANY_BELOW: [AtomicBool; 2] = [const { AtomicBool::new(false) }; 2];
+static PENDING: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+// Per heap: the last free figure seen here, readable without a heap lock; `usize::MAX` until then.
+static LAST_FREE: [AtomicUsize; 2] = [const { AtomicUsize::new(usize::MAX) }; 2];
+
+fn slot(heap: TraceHeap) -> usize {
+    match heap {
+        TraceHeap::Internal => 0,
+        TraceHeap::Psram => 1,
+    }
+}
+
+fn refresh(watermarks: &[Option<Watermark>; MAX_WATERMARKS], heap: TraceHeap) {
+    let of_heap = || watermarks.iter().flatten().filter(|w| w.heap == heap);
+    let highest = of_heap().map(|w| w.level).max().unwrap_or(0);
+    HIGHEST[slot(heap)].store(highest, Ordering::SeqCst);
+    ANY_BELOW[slot(heap)].store(of_heap().any(|w| w.below), Ordering::SeqCst);
+}
+
+/// Calls `callback` once each time free memory on `heap` drops below `watermark`.
+/// Callbacks run from [`pressure_task`] or [`check_pressure`], never from inside the allocator.
+///
+/// Returns `None` when the table is full.
+pub fn register_low_memory(
+    heap: TraceHeap,
+    watermark: usize,
+    callback: PressureCallback,
+) -> Option<WatermarkId> {
+    WATERMARKS.lock(|watermarks| {
+        let mut watermarks = watermarks.borrow_mut();
+        let id = watermarks.iter().position(|w| w.is_none())?;
+        watermarks[id] = Some(Watermark {
+            heap,
+            level: watermark,
+            callback,
+            below: false,
+            pending: None,
+        });
+        refresh(&watermarks, heap);
+        Some(WatermarkId(id))
+    })
+}
+
+pub fn unregister_low_memory(id: WatermarkId) {
+    WATERMARKS.lock(|watermarks| {
+        let mut watermarks = watermarks.borrow_mut();
+        if let Some(watermark) = watermarks[id.0].take() {
+            refresh(&watermarks, watermark.heap);
+        }
+    });
+}
+
+/// Called by the allocators after every allocation and free; `free` is only evaluated
+/// when a watermark on `heap` could have been crossed.
+pub(crate) fn note_free(heap: TraceHeap, free: impl FnOnce() -> usize) {
+    let slot = slot(heap);
+    let highest = HIGHEST[slot].load(Ordering::Relaxed);
+    if highest == 0 {
+        return;
+    }
+    let free = free();
+    LAST_FREE[slot].store(free, Ordering::Relaxed);
+    if free >= highest && !ANY_BELOW[slot].load(Ordering::Relaxed) {
+        return;
+    }
+    let fired = WATERMARKS.lock(|watermarks| {
+        let mut watermarks = watermarks.borrow_mut();
+        let mut fired = false;
+        for watermark in watermarks.iter_mut().flatten().filter(|w| w.heap == heap) {
+            if !watermark.below && free < watermark.level {
+                watermark.below = true;
+                watermark.pending = Some(free);
+                fired = true;
+            } else if watermark.below && free >= watermark.level {
+                watermark.below = false;
+            }
+        }
+        refresh(&watermarks, heap);
+        fired
+    });
+    if fired {
+        PENDING.signal(());
+    }
+}
+
+/// Free memory on `heap` as last sampled by [`pressure_task`] or a watermark check. Takes no lock,
+/// so it is safe from the panic handler; `None` before the first sample.
+pub fn last_free(heap: TraceHeap) -> Option<usize> {
+    let free = LAST_FREE[slot(heap)].load(Ordering::Relaxed);
+    (free != usize::MAX).then_some(free)
+}
+
+/// Runs the callbacks of every watermark crossed since the last check.
+pub fn check_pressure() {
+    for id in 0..MAX_WATERMARKS {
+        let due = WATERMARKS.lock(|watermarks| {
+            let mut watermarks = watermarks.borrow_mut();
+            let watermark = watermarks[id].as_mut()?;
+            let free = watermark.pending.take()?;
+            Some((
+                watermark.callback,
+                PressureEvent {
+                    heap: watermark.heap,
+                    watermark: watermark.level,
+                    free,
+                },
+            ))
+        });
+        if let Some((callback, event)) = due {
+            callback(&event);
+        }
+    }
+}
+
+/// Runs the callbacks as crossings are signalled, and samples both heaps every half second to
+/// catch memory taken from the raw heaps without going through the wrappers, e.g. by C code.
+#[task]
+pub async fn pressure_task() {
+    loop {
+        let mut crossed = pin!(PENDING.wait());
+        let mut tick = pin!(delay_ns_async(SAMPLE_INTERVAL));
+        poll_fn(|cx| {
+            if crossed.as_mut().poll(cx).is_ready() || tick.as_mut().poll(cx).is_ready() {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        })
+        .await;
+        for heap in [TraceHeap::Internal, TraceHeap::Psram] {
+            let free = mem::heap_free(heap);
+            LAST_FREE[slot(heap)].store(free, Ordering::Relaxed);
+            note_free(heap, || free);
+        }
+        check_pressure();
+    }
+}
@@ -0,0 +1,184 @@
+use super::mem::PSRAM_ALLOCATOR;
+use super::stats::MemStats;
+use alloc::vec::Vec;
+use core::alloc::{AllocError, Allocator, Layout};
+use core::cell::RefCell;
+use core::ptr::NonNull;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use embassy_sync::blocking_mutex::CriticalSectionMutex;
+
+const MAX_POOLS: usize = 8;
+const BLOCK_ALIGN: usize = 16;
+
+struct FreeList {
+    // Address of the first free block; each free block stores the address of the next one.
+    head: usize,
+    used: usize,
+    high_water: usize,
+}
+
+/// Fixed-size block pool carved out of one PSRAM allocation.
+/// Blocks that do not fit, or arrive once the pool is exhausted, go to [`PSRAM_ALLOCATOR`].
+pub struct SlabPool {
+    name: &'static str,
+    block_size: usize,
+    blocks: usize,
+    base: AtomicUsize,
+    free: CriticalSectionMutex<RefCell<FreeList>>,
+    allocations: AtomicUsize,
+    fallbacks: AtomicUsize,
+}
+
+static POOLS: CriticalSectionMutex<RefCell<[Option<&'static SlabPool>; MAX_POOLS]>> =
+    CriticalSectionMutex::new(RefCell::new([None; MAX_POOLS]));
+
+impl SlabPool {
+    pub const fn new(name: &'static str, block_size: usize, blocks: usize) -> Self {
+        let size = if block_size < size_of::<usize>() {
+            size_of::<usize>()
+        } else {
+            block_size
+        };
+        Self {
+            name,
+            block_size: (size + BLOCK_ALIGN - 1) & !(BLOCK_ALIGN - 1),
+            blocks,
+            base: AtomicUsize::new(0),
+            free: CriticalSectionMutex::new(RefCell::new(FreeList {
+                head: 0,
+                used: 0,
+                high_water: 0,
+            })),
+            allocations: AtomicUsize::new(0),
+            fallbacks: AtomicUsize::new(0),
+        }
+    }
+
+    fn layout(&self) -> Layout {
+        Layout::from_size_align(self.block_size * self.blocks, BLOCK_ALIGN).unwrap()
+    }
+
+    /// Reserves the pool's memory and makes it visible to [`pool_stats`]. Cheap once it succeeded;
+    /// until then every allocation falls back to [`PSRAM_ALLOCATOR`].
+    pub fn init(&'static self) -> bool {
+        if self.base.load(Ordering::Acquire) != 0 {
+            return true;
+        }
+        let Ok(region) = PSRAM_ALLOCATOR.allocate(self.layout()) else {
+            return false;
+        };
+        let base = region.as_ptr() as *mut u8 as usize;
+        let won = self.free.lock(|free| {
+            if self.base.load(Ordering::Acquire) != 0 {
+                return false;
+            }
+            let mut free = free.borrow_mut();
+            for index in (0..self.blocks).rev() {
+                let block = base + index * self.block_size;
+                unsafe { (block as *mut usize).write(free.head) };
+                free.head = block;
+            }
+            self.base.store(base, Ordering::Release);
+            true
+        });
+        if !won {
+            unsafe { PSRAM_ALLOCATOR.deallocate(region.cast(), self.layout()) };
+            return true;
+        }
+        POOLS.lock(|pools| {
+            if let Some(slot) = pools.borrow_mut().iter_mut().find(|slot| slot.is_none()) {
+                *slot = Some(self);
+            }
+        });
+        true
+    }
+
+    fn contains(&self, ptr: NonNull<u8>) -> bool {
+        let base = self.base.load(Ordering::Acquire);
+        let addr = ptr.as_ptr() as usize;
+        base != 0 && addr >= base && addr < base + self.block_size * self.blocks
+    }
+
+    fn pop(&self) -> Option<NonNull<u8>> {
+        self.free.lock(|free| {
+            let mut free = free.borrow_mut();
+            let block = NonNull::new(free.head as *mut u8)?;
+            free.head = unsafe { (free.head as *const usize).read() };
+            free.used += 1;
+            free.high_water = free.high_water.max(free.used);
+            Some(block)
+        })
+    }
+
+    fn push(&self, block: NonNull<u8>) {
+        self.free.lock(|free| {
+            let mut free = free.borrow_mut();
+            unsafe { (block.as_ptr() as *mut usize).write(free.head) };
+            free.head = block.as_ptr() as usize;
+            free.used -= 1;
+        })
+    }
+
+    pub fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    /// Allocations served from the pool itself.
+    pub fn allocations(&self) -> usize {
+        self.allocations.load(Ordering::Relaxed)
+    }
+
+    /// Allocations that did not fit a block or found the pool empty.
+    pub fn fallbacks(&self) -> usize {
+        self.fallbacks.load(Ordering::Relaxed)
+    }
+
+    pub fn stats(&self) -> MemStats {
+        let (used, high_water) = self.free.lock(|free| {
+            let free = free.borrow();
+            (free.used, free.high_water)
+        });
+        let blocks = if self.base.load(Ordering::Acquire) != 0 {
+            self.blocks
+        } else {
+            0
+        };
+        let free = blocks - used;
+        MemStats {
+            name: self.name,
+            size: blocks * self.block_size,
+            used: used * self.block_size,
+            free: free * self.block_size,
+            largest_free: Some(if free > 0 { self.block_size } else { 0 }),
+            high_water: high_water * self.block_size,
+        }
+    }
+}
+
+unsafe impl Allocator for SlabPool {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        if layout.size() <= self.block_size
+            && layout.align() <= BLOCK_ALIGN
+            && let Some(block) = self.pop()
+        {
+            self.allocations.fetch_add(1, Ordering::Relaxed);
+            return Ok(NonNull::slice_from_raw_parts(block, self.block_size));
+        }
+        self.fallbacks.fetch_add(1, Ordering::Relaxed);
+        PSRAM_ALLOCATOR.allocate(layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        if self.contains(ptr) {
+            self.push(ptr);
+        } else {
+            unsafe { PSRAM_ALLOCATOR.deallocate(ptr, layout) };
+        }
+    }
+}
+
+/// Usage of every initialised [`SlabPool`], one row per pool.
+pub fn pool_stats() -> Vec<MemStats> {
+    let pools = POOLS.lock(|pools| *pools.borrow());
+    pools.iter().flatten().map(|pool| pool.stats()).collect()
+}
@@ -0,0 +1,122 @@
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use core::fmt::{Display, Formatter};
+use embassy_sync::blocking_mutex::CriticalSectionMutex;
+
+const MAX_STACKS: usize = 4;
+const PAINT: u32 = 0xA5A5_A5A5;
+// Left unpainted at the bottom: esp-hal keeps its stack guard word in there.
+const GUARD_SKIP: usize = 64;
+
+/// Peak depth a painted stack has reached so far.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct StackUsage {
+    pub name: &'static str,
+    pub size: usize,
+    pub peak: usize,
+}
+
+impl StackUsage {
+    pub fn headroom(&self) -> usize {
+        self.size.saturating_sub(self.peak)
+    }
+}
+
+impl Display for StackUsage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "stack={} size={} peak={} headroom={}",
+            self.name,
+            self.size,
+            self.peak,
+            self.headroom()
+        )
+    }
+}
+
+fn painted_bottom(bottom: usize) -> usize {
+    (bottom + GUARD_SKIP + 3) & !3
+}
+
+#[derive(Clone, Copy)]
+struct PaintedStack {
+    name: &'static str,
+    bottom: usize,
+    painted_top: usize,
+    top: usize,
+}
+
+impl PaintedStack {
+    fn usage(&self) -> StackUsage {
+        let mut untouched = painted_bottom(self.bottom);
+        while untouched + 4 <= self.painted_top
+            && unsafe { (untouched as *const u32).read_volatile() } == PAINT
+        {
+            untouched += 4;
+        }
+        StackUsage {
+            name: self.name,
+            size: self.top - self.bottom,
+            peak: self.top - untouched,
+        }
+    }
+}
+
+static STACKS: CriticalSectionMutex<RefCell<[Option<PaintedStack>; MAX_STACKS]>> =
+    CriticalSectionMutex::new(RefCell::new([None; MAX_STACKS]));
+
+/// Fills `[bottom, painted_top)` with a known pattern and tracks the stack `[bottom, top)` as `name`.
+///
+/// # Safety
+/// The painted range must belong to that stack and must not be in use while it is painted.
+#[inline(never)]
+pub unsafe fn paint_stack(name: &'static str, bottom: *mut u8, painted_top: *mut u8, top: *mut u8) {
+    let bottom = bottom as usize;
+    let painted_top = (painted_top as usize).min(top as usize) & !3;
+    let mut word = painted_bottom(bottom);
+    while word < painted_top {
+        unsafe { (word as *mut u32).write_volatile(PAINT) };
+        word += 4;
+    }
+    let painted = PaintedStack {
+        name,
+        bottom,
+        painted_top,
+        top: top as usize,
+    };
+    STACKS.lock(|stacks| {
+        let mut stacks = stacks.borrow_mut();
+        let slot = match stacks
+            .iter()
+            .position(|s| s.is_some_and(|s| s.name == name))
+        {
+            Some(slot) => Some(slot),
+            None => stacks.iter().position(Option::is_none),
+        };
+        if let Some(slot) = slot {
+            stacks[slot] = Some(painted);
+        }
+    });
+}
+
+/// Stops tracking `name`, for stacks that are about to go away.
+pub fn forget_stack(name: &str) {
+    STACKS.lock(|stacks| {
+        for slot in stacks.borrow_mut().iter_mut() {
+            if slot.is_some_and(|s| s.name == name) {
+                *slot = None;
+            }
+        }
+    });
+}
+
+/// Peak depth of every painted stack, measured by how much of the pattern has been overwritten.
+pub fn stack_usage() -> Vec<StackUsage> {
+    let mut usage = Vec::with_capacity(MAX_STACKS);
+    // Scan under the lock so a stack cannot be forgotten and released halfway through.
+    STACKS.lock(|stacks| {
+        usage.extend(stacks.borrow().iter().flatten().map(PaintedStack::usage));
+    });
+    usage
+}
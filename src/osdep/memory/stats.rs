@@ -0,0 +1,115 @@
+use core::fmt::{Display, Formatter};
+
+/// Point-in-time figures for one heap.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MemStats {
+    pub name: &'static str,
+    pub size: usize,
+    pub used: usize,
+    pub free: usize,
+    /// Unknown on the ESP32-S3 heaps, whose free lists esp_alloc does not expose.
+    pub largest_free: Option<usize>,
+    pub high_water: usize,
+}
+
+impl MemStats {
+    /// 0.0 when the free memory is one contiguous block, approaching 1.0 as it splinters.
+    pub fn fragmentation(&self) -> Option<f32> {
+        let largest_free = self.largest_free?;
+        Some(if self.free == 0 {
+            0.0
+        } else {
+            1.0 - largest_free as f32 / self.free as f32
+        })
+    }
+}
+
+// `-` for figures a heap cannot report.
+struct Maybe<T>(Option<T>);
+
+impl<T: Display> Display for Maybe<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match &self.0 {
+            Some(value) => value.fmt(f),
+            None => f.write_str("-"),
+        }
+    }
+}
+
+impl Display for MemStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "heap={} size={} used={} free={} largest_free={} frag={:.2} high_water={}",
+            self.name,
+            self.size,
+            self.used,
+            self.free,
+            Maybe(self.largest_free),
+            Maybe(self.fragmentation()),
+            self.high_water
+        )
+    }
+}
+
+/// Renders several heaps at once: a table with the `tracing` feature, one key=value line per heap otherwise.
+pub struct MemStatsReport<'a>(pub &'a [MemStats]);
+
+#[cfg(feature = "tracing")]
+impl Display for MemStatsReport<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        use alloc::format;
+        use alloc::string::{String, ToString};
+        use alloc::vec::Vec;
+        use tabled::settings::Style;
+        use tabled::tables::CompactTable;
+
+        let header = [
+            "heap",
+            "size",
+            "used",
+            "free",
+            "largest free",
+            "frag",
+            "high water",
+        ]
+        .map(String::from);
+        let mut rows: Vec<[String; 7]> = Vec::with_capacity(self.0.len() + 1);
+        rows.push(header);
+        for stats in self.0 {
+            rows.push([
+                stats.name.to_string(),
+                stats.size.to_string(),
+                stats.used.to_string(),
+                stats.free.to_string(),
+                Maybe(stats.largest_free).to_string(),
+                format!("{:.2}", Maybe(stats.fragmentation())),
+                stats.high_water.to_string(),
+            ]);
+        }
+        let mut widths = [0usize; 7];
+        for row in &rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                // Cell text plus one space of padding on each side.
+                *width = (*width).max(cell.len() + 2);
+            }
+        }
+        CompactTable::new(&rows)
+            .columns(7)
+            .width(widths)
+            .with(Style::psql())
+            .fmt(f)
+    }
+}
+#[cfg(not(feature = "tracing"))]
+impl Display for MemStatsReport<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        for (i, stats) in self.0.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{stats}")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,148 @@
+use crate::fmt::Disp;
+use core::cell::RefCell;
+use core::fmt::{Display, Formatter};
+use embassy_sync::blocking_mutex::CriticalSectionMutex;
+
+pub const TRACE_CAPACITY: usize = 512;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TraceKind {
+    Alloc,
+    Free,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TraceHeap {
+    Internal,
+    Psram,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct TraceEvent {
+    pub kind: TraceKind,
+    pub ptr: usize,
+    pub size: usize,
+    pub align: usize,
+    pub heap: TraceHeap,
+    pub core: u8,
+    pub timestamp_ns: u64,
+}
+
+impl Display for TraceEvent {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{} {:?} {:?} ptr=0x{:x} size={} align={} cpu={}",
+            self.timestamp_ns, self.kind, self.heap, self.ptr, self.size, self.align, self.core
+        )
+    }
+}
+
+const EMPTY: TraceEvent = TraceEvent {
+    kind: TraceKind::Alloc,
+    ptr: 0,
+    size: 0,
+    align: 0,
+    heap: TraceHeap::Internal,
+    core: 0,
+    timestamp_ns: 0,
+};
+
+struct TraceRing {
+    events: [TraceEvent; TRACE_CAPACITY],
+    head: usize,
+    len: usize,
+    overwritten: usize,
+}
+
+static RING: CriticalSectionMutex<RefCell<TraceRing>> =
+    CriticalSectionMutex::new(RefCell::new(TraceRing {
+        events: [EMPTY; TRACE_CAPACITY],
+        head: 0,
+        len: 0,
+        overwritten: 0,
+    }));
+
+#[cfg(all(target_arch = "xtensa", target_os = "none"))]
+pub fn current_core() -> u8 {
+    esp_hal::system::Cpu::current() as u8
+}
+#[cfg(not(all(target_arch = "xtensa", target_os = "none")))]
+thread_local! {
+    static CORE: core::cell::Cell<u8> = const { core::cell::Cell::new(0) };
+}
+#[cfg(not(all(target_arch = "xtensa", target_os = "none")))]
+pub fn current_core() -> u8 {
+    CORE.with(|c| c.get())
+}
+/// Tags allocations made on this thread with an emulated core number.
+#[cfg(not(all(target_arch = "xtensa", target_os = "none")))]
+pub fn set_core_id(core: u8) {
+    CORE.with(|c| c.set(core));
+}
+
+/// Called from inside the allocators, so it must never allocate.
+#[inline(never)]
+pub(crate) fn record(kind: TraceKind, heap: TraceHeap, ptr: *const u8, size: usize, align: usize) {
+    let event = TraceEvent {
+        kind,
+        ptr: ptr as usize,
+        size,
+        align,
+        heap,
+        core: current_core(),
+        timestamp_ns: crate::osdep::time::epoch_ns(),
+    };
+    super::leak::track(kind, heap, event.ptr, size);
+    RING.lock(|ring| {
+        let mut ring = ring.borrow_mut();
+        let slot = (ring.head + ring.len) % TRACE_CAPACITY;
+        ring.events[slot] = event;
+        if ring.len == TRACE_CAPACITY {
+            ring.head = (ring.head + 1) % TRACE_CAPACITY;
+            ring.overwritten += 1;
+        } else {
+            ring.len += 1;
+        }
+    });
+}
+
+/// Removes the oldest recorded event.
+pub fn pop_trace() -> Option<TraceEvent> {
+    RING.lock(|ring| {
+        let mut ring = ring.borrow_mut();
+        if ring.len == 0 {
+            return None;
+        }
+        let event = ring.events[ring.head];
+        ring.head = (ring.head + 1) % TRACE_CAPACITY;
+        ring.len -= 1;
+        Some(event)
+    })
+}
+
+/// Hands every event recorded so far to `f`, oldest first, and returns how many
+/// were overwritten since the last drain because the buffer was full.
+pub fn drain_trace(mut f: impl FnMut(&TraceEvent)) -> usize {
+    let (pending, overwritten) = RING.lock(|ring| {
+        let mut ring = ring.borrow_mut();
+        (ring.len, core::mem::take(&mut ring.overwritten))
+    });
+    // Pop one at a time so `f` runs outside the lock; anything it allocates is
+    // left in the buffer for the next drain.
+    for _ in 0..pending {
+        match pop_trace() {
+            Some(event) => f(&event),
+            None => break,
+        }
+    }
+    overwritten
+}
+
+pub fn dump_trace(comment: &str) {
+    info!("Allocation trace: {}", comment);
+    let overwritten = drain_trace(|event| info!("{}", Disp(event)));
+    if overwritten > 0 {
+        info!("{} older events were overwritten", overwritten);
+    }
+}
@@ -1,15 +1,21 @@
-use crate::osdep::mem::dump_mem_stats;
+use crate::fmt::Disp;
+use crate::osdep::mem::{dump_mem_stats, stack_usage, task_usage};
+use crate::osdep::time::delay_ns_async;
+use core::time::Duration;
 use embassy_executor::task;
-use embassy_time::{Duration, Timer};
 #[cfg(all(not(target_os = "espidf")))]
 pub const STACK_SIZE: usize = 16777216 / 4 / 4 / 4 - 65536;
 #[cfg(target_os = "espidf")]
 pub const STACK_SIZE: usize = 16384;
+pub mod crash;
+pub mod logger;
 mod memory;
 mod network;
+pub mod safe_mode;
 mod starter;
 pub mod startup;
 pub mod storage;
+pub mod string;
 pub mod time;
 
 use crate::osdep::storage::kv_store::{get_key, put_key};
@@ -18,75 +24,130 @@ pub use network::*;
 
 pub mod typedefs {
 
+    #[cfg(all(target_arch = "xtensa", target_os = "none"))]
     use crate::osdep::mem::{EspHeap, PSRAM_ALLOCATOR};
     use crate::osdep::statics::{StaticsValue, SystemStatics};
     use alloc::boxed::Box;
+    use alloc::collections::{BTreeMap, VecDeque};
     use alloc::sync::Arc;
+    use alloc::vec::Vec;
     use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
     use embassy_sync::rwlock::RwLock as EmbassyMutex;
     use std::prelude::v1::*;
 
+    pub use crate::osdep::string::Str;
+
+    pub fn from_str_in<I: ToString>(from: I) -> Str {
+        Str::from(from.to_string().as_str())
+    }
+    pub fn new_str() -> Str {
+        Str::new()
+    }
+
+    // Large collections go to PSRAM on the device and to the global allocator on hosted builds,
+    // where they are the plain alloc types and keep their serde impls.
+    #[cfg(all(target_arch = "xtensa", target_os = "none"))]
+    pub type PsramVec<T> = Vec<T, &'static EspHeap>;
+    #[cfg(all(target_arch = "xtensa", target_os = "none"))]
+    pub type PsramBox<T> = Box<T, &'static EspHeap>;
     #[cfg(all(target_arch = "xtensa", target_os = "none"))]
-    pub type Str = string_alloc::String<&'static EspHeap>;
-    #[cfg(not(any(target_os = "none", feature = "external_strings")))]
-    pub type Str = alloc::string::String;
-    #[cfg(all(not(target_os = "none"), feature = "external_strings"))]
-    pub type Str = string_alloc::String;
+    pub type PsramVecDeque<T> = VecDeque<T, &'static EspHeap>;
+    #[cfg(all(target_arch = "xtensa", target_os = "none"))]
+    pub type PsramBTreeMap<K, V> = BTreeMap<K, V, &'static EspHeap>;
+    #[cfg(not(all(target_arch = "xtensa", target_os = "none")))]
+    pub type PsramVec<T> = Vec<T>;
+    #[cfg(not(all(target_arch = "xtensa", target_os = "none")))]
+    pub type PsramBox<T> = Box<T>;
+    #[cfg(not(all(target_arch = "xtensa", target_os = "none")))]
+    pub type PsramVecDeque<T> = VecDeque<T>;
+    #[cfg(not(all(target_arch = "xtensa", target_os = "none")))]
+    pub type PsramBTreeMap<K, V> = BTreeMap<K, V>;
 
     #[cfg(all(target_arch = "xtensa", target_os = "none"))]
-    pub fn from_str_in<I: ToString>(from: I) -> Str {
-        Str::from_str_in(from.to_string().as_ref(), &PSRAM_ALLOCATOR)
+    pub fn new_vec<T>() -> PsramVec<T> {
+        Vec::new_in(&PSRAM_ALLOCATOR)
     }
     #[cfg(all(target_arch = "xtensa", target_os = "none"))]
-    pub fn new_str() -> Str {
-        Str::new_in(&PSRAM_ALLOCATOR)
+    pub fn vec_with_capacity<T>(capacity: usize) -> PsramVec<T> {
+        Vec::with_capacity_in(capacity, &PSRAM_ALLOCATOR)
     }
-    #[cfg(not(any(target_os = "none", feature = "external_strings")))]
-    pub fn from_str_in<I: ToString>(from: I) -> Str {
-        Str::from_iter(from.to_string().chars())
+    #[cfg(all(target_arch = "xtensa", target_os = "none"))]
+    pub fn new_box<T>(value: T) -> PsramBox<T> {
+        Box::new_in(value, &PSRAM_ALLOCATOR)
     }
-    #[cfg(not(any(target_os = "none", feature = "external_strings")))]
-    pub fn new_str() -> Str {
-        Str::new()
+    #[cfg(all(target_arch = "xtensa", target_os = "none"))]
+    pub fn new_vec_deque<T>() -> PsramVecDeque<T> {
+        VecDeque::new_in(&PSRAM_ALLOCATOR)
     }
-
-    #[cfg(all(not(target_os = "none"), feature = "external_strings"))]
-    pub fn from_str_in<I: ToString>(from: I) -> Str {
-        Str::from(from.to_string())
+    #[cfg(all(target_arch = "xtensa", target_os = "none"))]
+    pub fn new_btree_map<K, V>() -> PsramBTreeMap<K, V> {
+        BTreeMap::new_in(&PSRAM_ALLOCATOR)
     }
-    #[cfg(all(not(target_os = "none"), feature = "external_strings"))]
-    pub fn new_str() -> Str {
-        Str::from("")
+    #[cfg(not(all(target_arch = "xtensa", target_os = "none")))]
+    pub fn new_vec<T>() -> PsramVec<T> {
+        Vec::new()
+    }
+    #[cfg(not(all(target_arch = "xtensa", target_os = "none")))]
+    pub fn vec_with_capacity<T>(capacity: usize) -> PsramVec<T> {
+        Vec::with_capacity(capacity)
+    }
+    #[cfg(not(all(target_arch = "xtensa", target_os = "none")))]
+    pub fn new_box<T>(value: T) -> PsramBox<T> {
+        Box::new(value)
+    }
+    #[cfg(not(all(target_arch = "xtensa", target_os = "none")))]
+    pub fn new_vec_deque<T>() -> PsramVecDeque<T> {
+        VecDeque::new()
     }
+    #[cfg(not(all(target_arch = "xtensa", target_os = "none")))]
+    pub fn new_btree_map<K, V>() -> PsramBTreeMap<K, V> {
+        BTreeMap::new()
+    }
+    pub fn vec_from_iter<T>(iter: impl IntoIterator<Item = T>) -> PsramVec<T> {
+        let mut vec = new_vec();
+        vec.extend(iter);
+        vec
+    }
+
     pub type Channel<T> = whisk::Channel<T>;
     pub type Mutex<T> = EmbassyMutex<CriticalSectionRawMutex, T>;
 
     #[cfg(all(target_arch = "xtensa", target_os = "none"))]
     pub type Statics<'a> = StaticsValue<'a, esp_radio::wifi::WifiDevice<'static>>;
     #[cfg(not(all(target_os = "none")))]
-    pub type Statics<'a> = StaticsValue<'a>;
+    pub type Statics<'a> = StaticsValue;
     pub type GlobalStatics = Arc<Statics<'static>>;
     pub type SpawnerStatics = Arc<SystemStatics>;
     pub type InitFunc = Box<dyn FnOnce(GlobalStatics, SpawnerStatics)>;
 }
 pub mod statics {
     use crate::osdep::net::{DnsStack, Executor, TcpStack};
+    #[cfg(all(target_arch = "xtensa", target_os = "none"))]
     use crate::osdep::typedefs::Mutex;
+    #[cfg(all(target_arch = "xtensa", target_os = "none"))]
     use alloc::sync::Arc;
     use core::cell::RefCell;
     use core::sync::atomic::AtomicBool;
     use embassy_executor::Spawner;
     use embassy_sync::blocking_mutex::CriticalSectionMutex;
+    #[cfg(all(target_arch = "xtensa", target_os = "none"))]
     use embassy_sync::once_lock::OnceLock;
+    #[cfg(all(target_arch = "xtensa", target_os = "none"))]
     use esp_mbedtls::{Certificates, Tls, TlsReference};
     use static_cell::StaticCell;
 
+    #[cfg(all(target_arch = "xtensa", target_os = "none"))]
     pub struct NetworkStatics<'a> {
         pub stack: TcpStack,
         pub dns: DnsStack,
         pub tls: TlsReference<'a>,
         pub certs: Certificates<'a>,
     }
+    #[cfg(not(all(target_arch = "xtensa", target_os = "none")))]
+    pub struct NetworkStatics {
+        pub stack: TcpStack,
+        pub dns: DnsStack,
+    }
 
     pub struct SystemStatics {
         pub core0_spawner: CriticalSectionMutex<RefCell<Option<Spawner>>>,
@@ -108,8 +169,16 @@ pub mod statics {
         pub core0_net: NetworkStatics<'a>,
         pub core1_net: NetworkStatics<'a>,
     }
+    #[cfg(not(all(target_arch = "xtensa", target_os = "none")))]
+    pub struct StaticsValue {
+        pub booted: AtomicBool,
+        pub core0_net: NetworkStatics,
+        pub core1_net: NetworkStatics,
+    }
+    #[cfg(all(target_arch = "xtensa", target_os = "none"))]
     pub static TLS: OnceLock<Tls> = OnceLock::new();
 
+    #[cfg(all(target_arch = "xtensa", target_os = "none"))]
     pub static CERTS: OnceLock<Certificates> = OnceLock::new();
     pub static EXECUTOR: StaticCell<Executor> = StaticCell::new();
     pub static ALT_EXECUTOR: StaticCell<Executor> = StaticCell::new();
@@ -133,6 +202,14 @@ pub fn get_emergency_poweroff() -> bool {
 pub async fn spin_memory() {
     loop {
         dump_mem_stats("memory");
-        Timer::after(Duration::from_millis(1000)).await
+        if cfg!(feature = "mem_accounting") {
+            for usage in task_usage() {
+                info!("memory {}", Disp(usage));
+            }
+        }
+        for usage in stack_usage() {
+            info!("memory {}", Disp(usage));
+        }
+        delay_ns_async(Duration::from_millis(1000)).await
     }
 }
@@ -0,0 +1,10 @@
+use edge_nal_std::Stack;
+
+pub const NUM_CONNECTIONS: usize = 3;
+pub const TOTAL_CONNECTIONS: usize = crate::osdep::net::NUM_CONNECTIONS + 1;
+pub type Executor = embassy_executor::Executor;
+pub type TcpStack = Stack;
+pub type TcpSocket = edge_nal_std::TcpSocket;
+pub type TcpError = std::io::Error;
+
+pub type DnsStack = Stack;
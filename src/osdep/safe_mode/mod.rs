@@ -0,0 +1,96 @@
+#[cfg_attr(not(all(target_arch = "xtensa")), path = "safe_mode_hosted.rs")]
+#[cfg_attr(
+    all(target_arch = "xtensa", target_os = "none"),
+    path = "safe_mode_esp.rs"
+)]
+#[cfg_attr(
+    all(target_arch = "xtensa", target_os = "espidf"),
+    path = "safe_mode_idf.rs"
+)]
+mod safe_mode_inner;
+
+use crate::osdep::time::{delay_ns_async, epoch_ns};
+use crate::osdep::typedefs::GlobalStatics;
+use core::sync::atomic::{AtomicU8, AtomicU32, Ordering};
+use core::time::Duration;
+use embassy_executor::task;
+use safe_mode_inner::{load_boot_state, store_boot_state};
+
+const UNDECIDED: u8 = 0;
+const NORMAL: u8 = 1;
+const SAFE: u8 = 2;
+
+static MAX_RESETS: AtomicU32 = AtomicU32::new(5);
+static WINDOW_MS: AtomicU32 = AtomicU32::new(60_000);
+static MODE: AtomicU8 = AtomicU8::new(UNDECIDED);
+
+/// Persisted across resets: RTC fast memory on the device, the kv store on hosted builds.
+#[derive(Clone, Copy, Default)]
+struct BootState {
+    /// Boots in a row that have not reached `booted`, this one included.
+    count: u32,
+    /// How long the last of them has been up.
+    alive_ms: u32,
+}
+
+/// After `max_resets` boots in a row that each died within `window` without reaching `booted`,
+/// the next boot comes up in safe mode. Call before `startup_fn`.
+pub fn set_crash_loop_policy(max_resets: u32, window: Duration) {
+    MAX_RESETS.store(max_resets, Ordering::SeqCst);
+    WINDOW_MS.store(
+        window.as_millis().min(u32::MAX as u128) as u32,
+        Ordering::SeqCst,
+    );
+}
+
+/// Safe mode skips the application's `InitFunc` and only runs networking and `spin_memory`, the
+/// periodic memory report. It lasts one boot: reaching `booted` clears the count, so the next
+/// reset starts normally again, and a crash loop has to build up from zero before it returns.
+pub fn is_safe_mode() -> bool {
+    MODE.load(Ordering::SeqCst) == SAFE
+}
+
+/// Waits until [`crash_loop_guard`] has counted this boot, then reports the mode it picked.
+pub async fn wait_safe_mode() -> bool {
+    while MODE.load(Ordering::SeqCst) == UNDECIDED {
+        delay_ns_async(Duration::from_millis(10)).await;
+    }
+    is_safe_mode()
+}
+
+/// Counts this boot and decides between normal and safe mode. Until the system reaches `booted`
+/// it keeps the boot's uptime next to the count, so the next boot can tell a quick crash from one
+/// that came after the window and does not count.
+#[task]
+pub async fn crash_loop_guard(statics: GlobalStatics) {
+    let window_ms = WINDOW_MS.load(Ordering::SeqCst);
+    let previous = load_boot_state();
+    let resets = if previous.alive_ms < window_ms {
+        previous.count
+    } else {
+        0
+    };
+    let count = resets + 1;
+    store_boot_state(BootState { count, alive_ms: 0 });
+    let safe = count > MAX_RESETS.load(Ordering::SeqCst);
+    if safe {
+        warn!(
+            "{} resets without reaching booted, starting in safe mode",
+            resets
+        );
+    }
+    MODE.store(if safe { SAFE } else { NORMAL }, Ordering::SeqCst);
+
+    loop {
+        if statics.booted.load(Ordering::SeqCst) {
+            store_boot_state(BootState::default());
+            return;
+        }
+        let alive_ms = (epoch_ns() / 1_000_000).min(u32::MAX as u64) as u32;
+        store_boot_state(BootState { count, alive_ms });
+        if alive_ms >= window_ms {
+            return;
+        }
+        delay_ns_async(Duration::from_millis(1000)).await;
+    }
+}
@@ -0,0 +1,27 @@
+use super::BootState;
+use core::ptr::{addr_of, addr_of_mut};
+
+const MAGIC: u32 = u32::from_le_bytes(*b"XBS1");
+
+// Magic, count, uptime and a check word, kept across software resets like the crash record.
+#[esp_hal::ram(unstable(rtc_fast, persistent))]
+static mut BOOT_SLOT: [u32; 4] = [0; 4];
+
+/// The state left by the previous boot; a cold start finds garbage and reads as no resets.
+pub fn load_boot_state() -> BootState {
+    let [magic, count, alive_ms, check] = unsafe { addr_of!(BOOT_SLOT).read_volatile() };
+    if magic != MAGIC || check != magic ^ count ^ alive_ms {
+        return BootState::default();
+    }
+    BootState { count, alive_ms }
+}
+
+pub fn store_boot_state(state: BootState) {
+    let slot = [
+        MAGIC,
+        state.count,
+        state.alive_ms,
+        MAGIC ^ state.count ^ state.alive_ms,
+    ];
+    unsafe { addr_of_mut!(BOOT_SLOT).write_volatile(slot) };
+}
@@ -0,0 +1,23 @@
+use super::BootState;
+use crate::osdep::storage::kv_store::{get_key_sync, put_key_sync};
+use alloc::format;
+
+const BOOT_STATE_KEY: &str = "boot_state";
+
+pub fn load_boot_state() -> BootState {
+    let Some(value) = get_key_sync(BOOT_STATE_KEY) else {
+        return BootState::default();
+    };
+    let mut fields = value.split_whitespace().map(|field| field.parse().ok());
+    match (fields.next().flatten(), fields.next().flatten()) {
+        (Some(count), Some(alive_ms)) => BootState { count, alive_ms },
+        _ => BootState::default(),
+    }
+}
+
+pub fn store_boot_state(state: BootState) {
+    put_key_sync(
+        BOOT_STATE_KEY,
+        &format!("{} {}", state.count, state.alive_ms),
+    );
+}
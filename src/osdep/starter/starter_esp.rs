@@ -1,10 +1,13 @@
-use crate::LOG_LEVEL;
+use crate::LOG_FILTER;
+use crate::fmt::Dbg;
+use crate::netclients::edgenal_tls::io_buffers;
+use crate::osdep::logger::{LOGGER, NamedSpawn, set_log_filter};
+use crate::osdep::mem;
 use crate::osdep::network::net::*;
 use crate::osdep::startup::*;
 use crate::osdep::statics::{NetworkStatics, SystemStatics, TLS};
-use crate::osdep::time::delay_ns_async;
+use crate::osdep::time::{delay_ns_async, epoch_ns, set_wall_clock};
 use crate::osdep::typedefs::{GlobalStatics, InitFunc, Mutex, SpawnerStatics, Statics};
-use alloc::format;
 use alloc::string::{String, ToString};
 use alloc::sync::Arc;
 use core::cell::RefCell;
@@ -12,14 +15,15 @@ use core::net::Ipv4Addr;
 use core::ptr::addr_of_mut;
 use core::str::FromStr;
 use core::sync::atomic::{AtomicBool, Ordering};
-use edge_nal_embassy::TcpBuffers;
 use embassy_executor::{Spawner, task};
+use embassy_net::dns::DnsQueryType;
+use embassy_net::udp::{PacketMetadata, UdpSocket};
 use embassy_net::{Ipv4Cidr, Runner, Stack, StackResources, StaticConfigV4};
 use embassy_sync::blocking_mutex::CriticalSectionMutex;
+use embassy_time::with_timeout;
 use esp_hal::interrupt::software::{SoftwareInterrupt, SoftwareInterruptControl};
 use esp_hal::psram::PsramSize;
 use esp_hal::psram::SpiTimingConfigCoreClock::SpiTimingConfigCoreClock240m;
-use esp_hal::system::Cpu;
 use esp_hal::timer::systimer::SystemTimer;
 use esp_hal::{clock::CpuClock, rng::Rng, timer::timg::TimerGroup};
 use esp_mbedtls::{Certificates, Tls};
@@ -29,9 +33,14 @@ use esp_radio::wifi::{AuthMethod, CountryInfo};
 use esp_radio::wifi::{
     ModeConfig, ScanConfig, WifiController, WifiDevice, WifiEvent, WifiStationState,
 };
-use log::Record;
 
 esp_bootloader_esp_idf::esp_app_desc!();
+
+const NTP_SERVER: &str = "pool.ntp.org";
+// Seconds from the NTP era (1900) to the Unix epoch.
+const NTP_UNIX_OFFSET: u64 = 2_208_988_800;
+const SNTP_TIMEOUT: embassy_time::Duration = embassy_time::Duration::from_secs(5);
+
 macro_rules! mk_static {
     ($t:ty,$val:expr) => {{
         static STATIC_CELL: static_cell::StaticCell<$t> = static_cell::StaticCell::new();
@@ -48,58 +57,20 @@ pub fn boot_second_thread<'a>(
     int1: SoftwareInterrupt<'static, 1>,
     sys: SpawnerStatics,
 ) {
-    esp_rtos::start_second_core(
-        cpu_control,
-        int1,
-        unsafe { &mut *addr_of_mut!(APP_CORE_STACK) },
-        || {
-            second_core_fn(sys);
-        },
-    );
+    let stack = unsafe { &mut *addr_of_mut!(APP_CORE_STACK) };
+    let (bottom, top) = (stack.bottom() as *mut u8, stack.top() as *mut u8);
+    unsafe { mem::paint_stack("app_core", bottom, top, top) };
+    esp_rtos::start_second_core(cpu_control, int1, stack, || {
+        second_core_fn(sys);
+    });
 }
 
-struct FilteredEspLogger;
-impl log::Log for FilteredEspLogger {
-    fn enabled(&self, _metadata: &log::Metadata) -> bool {
-        true
-    }
-
-    fn log(&self, record: &Record) {
-        if let Some(path) = record.module_path() {
-            if !path.starts_with("xapi") && !path.contains("psram") {
-                return;
-            }
-            if path.contains("esp_radio::wifi::os_adapter") {
-                if false {
-                    if let Some(args) = Some(record.args()) {
-                        let path = format!("{}", args);
-                        if path.contains("wifi_int_disable")
-                            || path.contains("wifi_int_restore")
-                            || path.contains("coex")
-                        {
-                            return;
-                        }
-                    }
-                } else {
-                    return;
-                }
-            }
-        }
-        let core_num = Cpu::current();
-        println!(
-            "cpu={core_num:?} {}: {} - {}",
-            record.module_path().or(Some("root")).unwrap(),
-            record.level(),
-            record.args()
-        );
-    }
-
-    fn flush(&self) {}
-}
-fn init_logger(level: log::LevelFilter) {
+fn init_logger(filter: &str) {
     unsafe {
-        log::set_logger_racy(&FilteredEspLogger).unwrap();
-        log::set_max_level_racy(level);
+        log::set_logger_racy(&LOGGER).unwrap();
+    }
+    if let Err(e) = set_log_filter(filter) {
+        println!("{e}");
     }
 }
 
@@ -107,7 +78,8 @@ pub fn startup(
     _wifi_name: &'static str,
     _password: &'static str,
 ) -> (SpawnerStatics, GlobalStatics) {
-    init_logger(LOG_LEVEL);
+    mem::paint_main_stack();
+    init_logger(LOG_FILTER);
     let config = esp_hal::Config::default()
         .with_cpu_clock(CpuClock::max())
         .with_psram(esp_hal::psram::PsramConfig {
@@ -149,14 +121,14 @@ pub fn startup(
         seed,
     );
 
-    let tcp_buffers = mk_static!(TcpBuffs, TcpBuffers::new());
+    let tcp_buffers = io_buffers();
     let tls = TLS.get_or_init(|| {
         Tls::new(peripherals.SHA)
             .unwrap()
             .with_hardware_rsa(peripherals.RSA)
     });
     let certs = Certificates::new();
-    let alt_buffs = mk_static!(TcpBuffs, TcpBuffers::new());
+    let alt_buffs = io_buffers();
     let sys = Arc::new(SystemStatics {
         core0_spawner: CriticalSectionMutex::new(RefCell::new(None)),
         core1_spawner: CriticalSectionMutex::new(RefCell::new(None)),
@@ -215,20 +187,28 @@ pub(crate) async fn boot(
     if let Some(net) = net {
         if let Some(controller) = controller {
             if let Some(driver) = runner {
-                let _ = spawner.spawn(connection(
-                    controller.clone(),
-                    wifi_name.to_string(),
-                    password.to_string(),
-                ));
-                let _ = spawner.spawn(net_task(driver.clone()));
-                let _ = spawner.spawn(boot_net(net, statics_ref.clone()));
+                let _ = spawner.spawn_named(
+                    "wifi_connection",
+                    connection(
+                        controller.clone(),
+                        wifi_name.to_string(),
+                        password.to_string(),
+                    ),
+                );
+                let _ = spawner.spawn_named("net_task", net_task(driver.clone()));
+                let _ = spawner.spawn_named("mem_pressure", mem::pressure_task());
+                let _ = spawner.spawn_named("boot_net", boot_net(net, statics_ref.clone()));
                 while !statics_ref.booted.load(Ordering::SeqCst) {
                     delay_ns_async(core::time::Duration::from_millis(100)).await;
                 }
-                log::info!("setting up clients");
+                let _ = spawner.spawn_named("sntp", sntp(net));
+                info!("setting up clients");
                 println!("doing wrapper init");
                 spawner
-                    .spawn(startup_wrapper(init, statics_ref.clone(), sys.clone()))
+                    .spawn_named(
+                        "startup_wrapper",
+                        startup_wrapper(init, statics_ref.clone(), sys.clone()),
+                    )
                     .unwrap();
             }
         }
@@ -240,6 +220,18 @@ pub(crate) async fn connection(
     controller: Arc<Mutex<WifiController<'static>>>,
     wifi_name: String,
     password: String,
+) {
+    mem::tagged(
+        "connection",
+        run_connection(controller, wifi_name, password),
+    )
+    .await
+}
+
+async fn run_connection(
+    controller: Arc<Mutex<WifiController<'static>>>,
+    wifi_name: String,
+    password: String,
 ) {
     println!("start connection task");
     let mut controller = controller.write().await;
@@ -274,7 +266,7 @@ pub(crate) async fn connection(
                 .await
                 .unwrap();
             for entry in result {
-                log::info!("{:?}", entry);
+                info!("{}", Dbg(&entry));
             }
         }
         println!("About to connect...");
@@ -310,7 +302,68 @@ pub(crate) async fn boot_net(net: Stack<'static>, statics: GlobalStatics) {
 }
 #[task]
 pub(crate) async fn net_task(runner: Arc<Mutex<Runner<'static, WifiDevice<'static>>>>) {
+    mem::tagged("net_task", run_net(runner)).await
+}
+
+async fn run_net(runner: Arc<Mutex<Runner<'static, WifiDevice<'static>>>>) {
     println!("starting network task");
     let mut runner = runner.write().await;
     runner.run().await
 }
+
+/// Keeps the wall clock shown in log lines set from SNTP: retries every 30 s until the first valid
+/// answer, then resyncs hourly.
+#[task]
+pub(crate) async fn sntp(net: Stack<'static>) {
+    loop {
+        let answer = with_timeout(SNTP_TIMEOUT, query_sntp(net)).await;
+        let wait = if let Ok(Some(unix_ns)) = answer {
+            set_wall_clock(unix_ns);
+            3600
+        } else {
+            warn!("no valid SNTP answer from {}", NTP_SERVER);
+            30
+        };
+        delay_ns_async(core::time::Duration::from_secs(wait)).await;
+    }
+}
+
+// Unix time in nanoseconds from one SNTP v4 exchange.
+async fn query_sntp(net: Stack<'static>) -> Option<u64> {
+    let server = *net
+        .dns_query(NTP_SERVER, DnsQueryType::A)
+        .await
+        .ok()?
+        .first()?;
+    let (mut rx_meta, mut tx_meta) = ([PacketMetadata::EMPTY; 1], [PacketMetadata::EMPTY; 1]);
+    let (mut rx, mut tx) = ([0u8; 64], [0u8; 64]);
+    let mut socket = UdpSocket::new(net, &mut rx_meta, &mut rx, &mut tx_meta, &mut tx);
+    socket.bind(0).ok()?;
+    let mut packet = [0u8; 48];
+    // Leap indicator 0, version 4, client mode.
+    packet[0] = 0x23;
+    // The server echoes our transmit timestamp back as the originate timestamp; the uptime is
+    // enough to tell its answer from a stale or forged one.
+    let nonce = epoch_ns().to_be_bytes();
+    packet[40..48].copy_from_slice(&nonce);
+    socket.send_to(&packet, (server, 123)).await.ok()?;
+    let (len, _) = socket.recv_from(&mut packet).await.ok()?;
+    // Server mode, a synchronised leap indicator, stratum 1-15 (0 is a kiss-of-death) and our
+    // request's timestamp.
+    if len < 48
+        || packet[0] & 0x7 != 4
+        || packet[0] >> 6 == 3
+        || !(1..=15).contains(&packet[1])
+        || packet[24..32] != nonce
+    {
+        return None;
+    }
+    // Transmit timestamp: seconds since 1900 and a 32-bit binary fraction.
+    let secs = u32::from_be_bytes(packet[40..44].try_into().unwrap()) as u64;
+    let fraction = u32::from_be_bytes(packet[44..48].try_into().unwrap()) as u64;
+    if secs == 0 && fraction == 0 {
+        return None;
+    }
+    let unix_secs = secs.checked_sub(NTP_UNIX_OFFSET)?;
+    Some(unix_secs * 1_000_000_000 + (fraction * 1_000_000_000 >> 32))
+}
@@ -0,0 +1,122 @@
+use crate::LOG_FILTER;
+use crate::fmt::Dbg;
+use crate::osdep::logger::{LOGGER, NamedSpawn, set_log_filter};
+use crate::osdep::network::net::*;
+use crate::osdep::startup::*;
+use crate::osdep::statics::{NetworkStatics, SystemStatics};
+use crate::osdep::time::{delay_ns_async, set_wall_clock};
+use crate::osdep::typedefs::{GlobalStatics, InitFunc, SpawnerStatics, Statics};
+use alloc::sync::Arc;
+use core::cell::RefCell;
+use core::sync::atomic::{AtomicBool, Ordering};
+use edge_nal::{AddrType, Dns};
+use embassy_executor::{Spawner, task};
+use embassy_sync::blocking_mutex::CriticalSectionMutex;
+
+// Generous next to the device's APP_CORE_STACK: host formatting and backtraces are deeper.
+const APP_CORE_STACK_SIZE: usize = 512 * 1024;
+
+fn init_logger(filter: &str) {
+    let _ = log::set_logger(&LOGGER);
+    if let Err(e) = set_log_filter(filter) {
+        println!("{e}");
+    }
+}
+
+pub fn startup(
+    _wifi_name: &'static str,
+    _password: &'static str,
+) -> (SpawnerStatics, GlobalStatics) {
+    crate::osdep::mem::paint_main_stack();
+    init_logger(LOG_FILTER);
+    if let Ok(now) = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH) {
+        set_wall_clock(now.as_nanos() as u64);
+    }
+    let sys = Arc::new(SystemStatics {
+        core0_spawner: CriticalSectionMutex::new(RefCell::new(None)),
+        core1_spawner: CriticalSectionMutex::new(RefCell::new(None)),
+    });
+    let second = sys.clone();
+    std::thread::Builder::new()
+        .name("core1".into())
+        .stack_size(APP_CORE_STACK_SIZE)
+        .spawn(move || {
+            crate::osdep::mem::set_core_id(1);
+            crate::osdep::mem::paint_thread_stack("app_core", APP_CORE_STACK_SIZE / 2);
+            second_core_fn(second)
+        })
+        .unwrap();
+    debug!("about to return statics");
+    (
+        sys,
+        Arc::new(Statics {
+            booted: AtomicBool::new(false),
+            core0_net: NetworkStatics {
+                stack: TcpStack::new(),
+                dns: DnsStack::new(),
+            },
+            core1_net: NetworkStatics {
+                stack: TcpStack::new(),
+                dns: DnsStack::new(),
+            },
+        }),
+    )
+}
+
+#[task]
+pub(crate) async fn boot(
+    spawner: Spawner,
+    sys: SpawnerStatics,
+    statics_ref: GlobalStatics,
+    _wifi_name: &'static str,
+    _password: &'static str,
+    init: InitFunc,
+) {
+    sys.core0_spawner.lock(|core0_spawner| {
+        let _ = core0_spawner.replace(Some(spawner.clone()));
+    });
+    loop {
+        if sys.core1_spawner.lock(|x| x.borrow().is_none()) {
+            delay_ns_async(core::time::Duration::from_millis(10)).await;
+        } else {
+            break;
+        }
+    }
+    let _ = spawner.spawn_named("boot_net", boot_net(statics_ref.clone()));
+    let _ = spawner.spawn_named("mem_pressure", crate::osdep::mem::pressure_task());
+    while !statics_ref.booted.load(Ordering::SeqCst) {
+        delay_ns_async(core::time::Duration::from_millis(100)).await;
+    }
+    info!("setting up clients");
+    debug!("doing wrapper init");
+    spawner
+        .spawn_named(
+            "startup_wrapper",
+            startup_wrapper(init, statics_ref.clone(), sys.clone()),
+        )
+        .unwrap();
+}
+
+#[task]
+pub(crate) async fn boot_net(statics: GlobalStatics) {
+    info!("waiting for host network");
+    loop {
+        match statics
+            .core0_net
+            .dns
+            .get_host_by_name("localhost", AddrType::IPv4)
+            .await
+        {
+            Ok(addr) => {
+                info!("network configuration: {}", Dbg(addr));
+                break;
+            }
+            Err(e) => {
+                warn!("host network not ready: {}", Dbg(e));
+                delay_ns_async(core::time::Duration::from_millis(1000)).await;
+            }
+        }
+    }
+    info!("network configured");
+    statics.booted.store(true, Ordering::SeqCst);
+}
@@ -1,4 +1,12 @@
+use crate::fmt::Disp;
+use crate::osdep::crash::{install_crash_handler, take_crash_record};
+use crate::osdep::logger::{
+    DEFAULT_HISTORY, NamedSpawn, enable_log_history, log_drain, restore_log_filter,
+};
+use crate::osdep::mem::TraceHeap;
 use crate::osdep::net::Executor;
+use crate::osdep::safe_mode::{crash_loop_guard, wait_safe_mode};
+use crate::osdep::spin_memory;
 use crate::osdep::starter::{boot, startup};
 use crate::osdep::statics::{ALT_EXECUTOR, EXECUTOR};
 use crate::osdep::typedefs::{GlobalStatics, InitFunc, SpawnerStatics};
@@ -7,28 +15,46 @@ use embassy_executor::task;
 
 #[task]
 pub async fn startup_wrapper(init: InitFunc, statics: GlobalStatics, sys: SpawnerStatics) {
-    log::info!("startup_wrapper startup");
+    info!("startup_wrapper startup");
     loop {
         if statics.booted.load(Ordering::SeqCst) {
             break;
         }
         crate::osdep::time::delay_ns_async(core::time::Duration::from_millis(100)).await;
     }
-    log::info!("startup_wrapper booted");
+    info!("startup_wrapper booted");
+    if wait_safe_mode().await {
+        warn!("safe mode: skipping application init");
+        if let Some(spawner) = sys.core0_spawner.lock(|spawner| spawner.borrow().clone()) {
+            let _ = spawner.spawn_named("spin_memory", spin_memory());
+        }
+        return;
+    }
     init(statics, sys);
 }
 
 pub fn second_core_fn(sys: SpawnerStatics) {
     let executor = ALT_EXECUTOR.init(Executor::new());
     executor.run(|spawner| {
-        log::info!("second core started");
+        info!("second core started");
         sys.core1_spawner.lock(|x| x.replace(Some(spawner)));
     });
 }
 pub fn startup_fn(wifi_name: &'static str, password: &'static str, init: InitFunc) {
+    install_crash_handler();
     let (sys, statics) = startup(wifi_name, password);
+    enable_log_history(DEFAULT_HISTORY, TraceHeap::Psram);
+    if let Some(record) = take_crash_record() {
+        error!("previous run crashed:\n{}", Disp(record));
+    }
     let executor = EXECUTOR.init(Executor::new());
     executor.run(|spawner| {
-        let _ = spawner.spawn(boot(spawner, sys, statics, wifi_name, password, init));
+        let _ = spawner.spawn_named("log_drain", log_drain());
+        let _ = spawner.spawn_named("restore_log_filter", restore_log_filter());
+        let _ = spawner.spawn_named("crash_loop_guard", crash_loop_guard(statics.clone()));
+        let _ = spawner.spawn_named(
+            "boot",
+            boot(spawner, sys, statics, wifi_name, password, init),
+        );
     });
 }
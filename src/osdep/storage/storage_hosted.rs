@@ -0,0 +1,142 @@
+use crate::fmt::Disp;
+use alloc::format;
+use alloc::string::String;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+#[derive(Clone, Debug)]
+pub enum StoreLocation {
+    /// One file per key inside the directory.
+    Directory(PathBuf),
+    /// Every key in a single `key\tvalue` file.
+    File(PathBuf),
+}
+
+static LOCATION: Mutex<Option<StoreLocation>> = Mutex::new(None);
+
+/// Points the store somewhere else; by default `XAPI_KV_FILE`, then `XAPI_KV_DIR`, then `./.kv_store`.
+pub fn set_location(location: StoreLocation) {
+    LOCATION.lock().unwrap().replace(location);
+}
+
+fn location() -> StoreLocation {
+    LOCATION
+        .lock()
+        .unwrap()
+        .get_or_insert_with(|| {
+            if let Some(file) = std::env::var_os("XAPI_KV_FILE") {
+                StoreLocation::File(file.into())
+            } else if let Some(dir) = std::env::var_os("XAPI_KV_DIR") {
+                StoreLocation::Directory(dir.into())
+            } else {
+                StoreLocation::Directory(".kv_store".into())
+            }
+        })
+        .clone()
+}
+
+pub async fn get_key(key: &str) -> Option<String> {
+    get_key_sync(key)
+}
+pub async fn put_key(key: &str, value: &str) {
+    put_key_sync(key, value)
+}
+
+pub fn get_key_sync(key: &str) -> Option<String> {
+    match location() {
+        StoreLocation::Directory(dir) => fs::read_to_string(dir.join(encode_key(key))).ok(),
+        StoreLocation::File(file) => read_file(&file).remove(key),
+    }
+}
+pub fn put_key_sync(key: &str, value: &str) {
+    let location = location();
+    let _guard = WRITE_LOCK.lock().unwrap();
+    let result = match location {
+        StoreLocation::Directory(dir) => fs::create_dir_all(&dir)
+            .and_then(|_| write_atomic(&dir.join(encode_key(key)), value.as_bytes())),
+        StoreLocation::File(file) => {
+            let mut entries = read_file(&file);
+            entries.insert(key.into(), value.into());
+            let mut out = String::new();
+            for (k, v) in entries {
+                out.push_str(&escape(&k));
+                out.push('\t');
+                out.push_str(&escape(&v));
+                out.push('\n');
+            }
+            write_atomic(&file, out.as_bytes())
+        }
+    };
+    if let Err(e) = result {
+        warn!("kv_store: failed to write {}: {}", key, Disp(e));
+    }
+}
+
+static WRITE_LOCK: Mutex<()> = Mutex::new(());
+
+fn write_atomic(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(format!(".tmp{}", std::process::id()));
+    let tmp = PathBuf::from(tmp);
+    {
+        let mut file = fs::File::create(&tmp)?;
+        file.write_all(contents)?;
+        file.sync_all()?;
+    }
+    fs::rename(&tmp, path)
+}
+
+fn read_file(path: &Path) -> BTreeMap<String, String> {
+    let Ok(contents) = fs::read_to_string(path) else {
+        return BTreeMap::new();
+    };
+    contents
+        .lines()
+        .filter_map(|line| line.split_once('\t'))
+        .map(|(k, v)| (unescape(k), unescape(v)))
+        .collect()
+}
+
+fn encode_key(key: &str) -> String {
+    let mut out = String::with_capacity(key.len());
+    for b in key.bytes() {
+        if b.is_ascii_alphanumeric() || b == b'_' || b == b'-' {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{b:02X}"));
+        }
+    }
+    out
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+fn unescape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => out.push('\t'),
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some(other) => out.push(other),
+            None => out.push('\\'),
+        }
+    }
+    out
+}
@@ -0,0 +1,177 @@
+use core::borrow::Borrow;
+use core::fmt::{self, Debug, Display, Formatter};
+use core::hash::{Hash, Hasher};
+use core::ops::Deref;
+use serde::de::{Deserialize, Deserializer, Visitor};
+use serde::ser::{Serialize, Serializer};
+
+#[cfg(all(target_arch = "xtensa", target_os = "none"))]
+mod backing {
+    use crate::osdep::mem::{EspHeap, PSRAM_ALLOCATOR};
+
+    pub type Inner = string_alloc::String<&'static EspHeap>;
+    pub fn empty() -> Inner {
+        Inner::new_in(&PSRAM_ALLOCATOR)
+    }
+    pub fn from_str(s: &str) -> Inner {
+        Inner::from_str_in(s, &PSRAM_ALLOCATOR)
+    }
+}
+#[cfg(not(any(target_os = "none", feature = "external_strings")))]
+mod backing {
+    pub type Inner = alloc::string::String;
+    pub fn empty() -> Inner {
+        Inner::new()
+    }
+    pub fn from_str(s: &str) -> Inner {
+        Inner::from(s)
+    }
+}
+#[cfg(all(not(target_os = "none"), feature = "external_strings"))]
+mod backing {
+    pub type Inner = string_alloc::String;
+    pub fn empty() -> Inner {
+        Inner::from("")
+    }
+    pub fn from_str(s: &str) -> Inner {
+        Inner::from(s)
+    }
+}
+
+/// Owned string that lives in PSRAM on the device; same API on every backend.
+#[derive(Clone)]
+pub struct Str(backing::Inner);
+
+impl Str {
+    pub fn new() -> Self {
+        Str(backing::empty())
+    }
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+    pub fn push_str(&mut self, s: &str) {
+        self.0.push_str(s);
+    }
+    pub fn push(&mut self, c: char) {
+        self.0.push(c);
+    }
+    pub fn clear(&mut self) {
+        self.0.clear();
+    }
+}
+
+impl Default for Str {
+    fn default() -> Self {
+        Str::new()
+    }
+}
+
+impl Deref for Str {
+    type Target = str;
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<str> for Str {
+    fn as_ref(&self) -> &str {
+        self
+    }
+}
+
+impl Borrow<str> for Str {
+    fn borrow(&self) -> &str {
+        self
+    }
+}
+
+impl From<&str> for Str {
+    fn from(s: &str) -> Self {
+        Str(backing::from_str(s))
+    }
+}
+
+impl fmt::Write for Str {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.push_str(s);
+        Ok(())
+    }
+}
+
+impl Display for Str {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Display::fmt(self.as_str(), f)
+    }
+}
+
+impl Debug for Str {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl PartialEq for Str {
+    fn eq(&self, other: &Str) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+impl Eq for Str {}
+
+impl PartialEq<str> for Str {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for Str {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl PartialEq<Str> for &str {
+    fn eq(&self, other: &Str) -> bool {
+        *self == other.as_str()
+    }
+}
+
+impl PartialOrd for Str {
+    fn partial_cmp(&self, other: &Str) -> Option<core::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Str {
+    fn cmp(&self, other: &Str) -> core::cmp::Ordering {
+        self.as_str().cmp(other.as_str())
+    }
+}
+
+// Hashes like `str` so `Borrow<str>` lookups agree.
+impl Hash for Str {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_str().hash(state);
+    }
+}
+
+impl Serialize for Str {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Str {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct StrVisitor;
+        impl Visitor<'_> for StrVisitor {
+            type Value = Str;
+            fn expecting(&self, f: &mut Formatter<'_>) -> fmt::Result {
+                f.write_str("a string")
+            }
+            fn visit_str<E: serde::de::Error>(self, v: &str) -> Result<Str, E> {
+                Ok(Str::from(v))
+            }
+        }
+        deserializer.deserialize_str(StrVisitor)
+    }
+}
@@ -6,3 +6,19 @@
 )]
 pub mod time;
 pub use time::*;
+mod wall;
+pub use wall::*;
+
+/// Lets the other tasks on the executor run once, on the real and the virtual clock alike.
+pub async fn yield_now() {
+    let mut yielded = false;
+    core::future::poll_fn(|cx| {
+        if yielded {
+            return core::task::Poll::Ready(());
+        }
+        yielded = true;
+        cx.waker().wake_by_ref();
+        core::task::Poll::Pending
+    })
+    .await
+}
@@ -0,0 +1,130 @@
+use core::future::poll_fn;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use core::task::{Poll, Waker};
+use core::time::Duration;
+use embassy_time::Timer;
+use std::sync::{LazyLock, Mutex};
+use std::time::Instant;
+
+static START: LazyLock<Instant> = LazyLock::new(Instant::now);
+static VIRTUAL: AtomicBool = AtomicBool::new(false);
+static VIRTUAL_NS: AtomicU64 = AtomicU64::new(0);
+static SLEEPERS: Mutex<Vec<(u64, Waker)>> = Mutex::new(Vec::new());
+
+fn real_ns() -> u64 {
+    START.elapsed().as_nanos() as u64
+}
+
+pub fn epoch_ns() -> u64 {
+    if VIRTUAL.load(Ordering::SeqCst) {
+        VIRTUAL_NS.load(Ordering::SeqCst)
+    } else {
+        // Stays put after the virtual clock ran ahead, until real time catches up.
+        real_ns().max(VIRTUAL_NS.load(Ordering::SeqCst))
+    }
+}
+pub async fn delay_ns_async(us: Duration) {
+    if !VIRTUAL.load(Ordering::SeqCst) {
+        Timer::after(embassy_time::Duration::from_nanos(us.as_nanos() as u64)).await;
+        return;
+    }
+    let deadline = epoch_ns().saturating_add(us.as_nanos() as u64);
+    poll_fn(|cx| {
+        if !VIRTUAL.load(Ordering::SeqCst) || VIRTUAL_NS.load(Ordering::SeqCst) >= deadline {
+            return Poll::Ready(());
+        }
+        let mut sleepers = SLEEPERS.lock().unwrap();
+        if !sleepers
+            .iter()
+            .any(|(d, w)| *d == deadline && w.will_wake(cx.waker()))
+        {
+            sleepers.push((deadline, cx.waker().clone()));
+        }
+        Poll::Pending
+    })
+    .await
+}
+
+/// Switches between the wall clock and the virtual clock.
+///
+/// The virtual clock resumes from the later of its last value and the real uptime, and the wall
+/// clock holds at the virtual time until it passes it, so `epoch_ns` never goes backwards.
+/// Switching back to the wall clock releases every pending virtual sleeper.
+pub fn use_virtual_clock(enabled: bool) {
+    if enabled {
+        VIRTUAL_NS.fetch_max(real_ns(), Ordering::SeqCst);
+        VIRTUAL.store(true, Ordering::SeqCst);
+    } else {
+        VIRTUAL.store(false, Ordering::SeqCst);
+        let sleepers = core::mem::take(&mut *SLEEPERS.lock().unwrap());
+        for (_, waker) in sleepers {
+            waker.wake();
+        }
+    }
+}
+pub fn is_virtual_clock() -> bool {
+    VIRTUAL.load(Ordering::SeqCst)
+}
+
+/// Moves the virtual clock forward and wakes every `delay_ns_async` whose deadline has passed.
+pub fn advance_clock(by: Duration) {
+    let now = VIRTUAL_NS.fetch_add(by.as_nanos() as u64, Ordering::SeqCst) + by.as_nanos() as u64;
+    wake_expired(now);
+}
+
+/// Jumps the virtual clock to the earliest pending deadline, if there is one.
+///
+/// Returns `false` when nothing is sleeping on the virtual clock.
+pub fn advance_to_next_deadline() -> bool {
+    let next = SLEEPERS.lock().unwrap().iter().map(|(d, _)| *d).min();
+    match next {
+        Some(deadline) => {
+            let now = VIRTUAL_NS.fetch_max(deadline, Ordering::SeqCst).max(deadline);
+            wake_expired(now);
+            true
+        }
+        None => false,
+    }
+}
+
+fn wake_expired(now: u64) {
+    let expired: Vec<Waker> = {
+        let mut sleepers = SLEEPERS.lock().unwrap();
+        let (expired, pending): (Vec<_>, Vec<_>) = core::mem::take(&mut *sleepers)
+            .into_iter()
+            .partition(|(d, _)| *d <= now);
+        *sleepers = pending;
+        expired.into_iter().map(|(_, w)| w).collect()
+    };
+    for waker in expired {
+        waker.wake();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::pin::pin;
+    use core::task::Context;
+
+    #[test]
+    fn virtual_clock_moves_only_when_advanced() {
+        use_virtual_clock(true);
+        let start = epoch_ns();
+        let mut cx = Context::from_waker(Waker::noop());
+        let mut sleep = pin!(delay_ns_async(Duration::from_millis(5)));
+        assert!(sleep.as_mut().poll(&mut cx).is_pending());
+
+        advance_clock(Duration::from_millis(4));
+        assert_eq!(epoch_ns(), start + 4_000_000);
+        assert!(sleep.as_mut().poll(&mut cx).is_pending());
+
+        assert!(advance_to_next_deadline());
+        assert_eq!(epoch_ns(), start + 5_000_000);
+        assert!(sleep.as_mut().poll(&mut cx).is_ready());
+        assert!(!advance_to_next_deadline());
+
+        use_virtual_clock(false);
+        assert!(epoch_ns() >= start + 5_000_000);
+    }
+}
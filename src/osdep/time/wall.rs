@@ -0,0 +1,51 @@
+use super::epoch_ns;
+use core::cell::Cell;
+use core::fmt::{Display, Formatter};
+use embassy_sync::blocking_mutex::CriticalSectionMutex;
+
+// Unix time at `epoch_ns() == 0`, once something has told us the time.
+static OFFSET_NS: CriticalSectionMutex<Cell<Option<u64>>> =
+    CriticalSectionMutex::new(Cell::new(None));
+
+/// Anchors the wall clock: `unix_ns` is the current time since the Unix epoch, e.g. from SNTP.
+pub fn set_wall_clock(unix_ns: u64) {
+    let offset = unix_ns.saturating_sub(epoch_ns());
+    OFFSET_NS.lock(|o| o.set(Some(offset)));
+}
+
+/// Unix time of the `epoch_ns` timestamp `at_ns`; `None` until [`set_wall_clock`] was called.
+pub fn wall_clock_at(at_ns: u64) -> Option<u64> {
+    OFFSET_NS.lock(|o| o.get()).map(|offset| offset + at_ns)
+}
+
+pub fn wall_clock_ns() -> Option<u64> {
+    wall_clock_at(epoch_ns())
+}
+
+/// Nanoseconds since the Unix epoch, shown as UTC in RFC 3339 with milliseconds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WallTime(pub u64);
+
+impl Display for WallTime {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        let secs = self.0 / 1_000_000_000;
+        let (days, rem) = ((secs / 86_400) as i64, secs % 86_400);
+        // Days to civil date, after Howard Hinnant's `civil_from_days`.
+        let z = days + 719_468;
+        let (era, doe) = (z.div_euclid(146_097), z.rem_euclid(146_097));
+        let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + (month <= 2) as i64;
+        write!(
+            f,
+            "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:03}Z",
+            rem / 3600,
+            rem / 60 % 60,
+            rem % 60,
+            self.0 / 1_000_000 % 1000
+        )
+    }
+}